@@ -0,0 +1,127 @@
+#ifndef DISPLAY_DISTRIBUTOR_H
+#define DISPLAY_DISTRIBUTOR_H
+
+/* Generated by cbindgen from src/capi.rs, do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+// The result of a call, negative on failure.
+typedef enum {
+  DD_STATUS_OK = 0,
+  DD_STATUS_INVALID_ARGUMENT = -1,
+  DD_STATUS_NO_SOCKET_PATH = -2,
+  DD_STATUS_UNSUPPORTED_VERSION = -3,
+  DD_STATUS_DISCONNECTED = -4,
+  // The server sent something the client doesn't understand.
+  DD_STATUS_PROTOCOL = -5,
+  DD_STATUS_SEAT_BUSY = -6,
+  DD_STATUS_NO_DISPLAYS = -7,
+  DD_STATUS_UNKNOWN_DISPLAY = -8,
+  DD_STATUS_DISPLAY_UNAVAILABLE = -9,
+  DD_STATUS_AMBIGUOUS_DISPLAY = -10,
+  DD_STATUS_DISPLAY_BUSY = -11,
+  DD_STATUS_LEASE_FAILED = -12,
+  DD_STATUS_NO_PERMISSION = -13,
+  // The server failed on its side.
+  DD_STATUS_SERVER = -14,
+  DD_STATUS_IO = -15,
+} dd_status;
+
+// What to do when some of the requested cards can't be leased.
+typedef enum {
+  DD_LEASE_MODE_ALL_OR_NOTHING = 0,
+  DD_LEASE_MODE_BEST_EFFORT = 1,
+} dd_lease_mode;
+
+// A connection to the server that has completed the handshake.
+typedef struct dd_client dd_client;
+
+// Granted displays, one lease fd per card.
+typedef struct dd_lease dd_lease;
+
+#ifdef __cplusplus
+extern "C" {
+#endif // __cplusplus
+
+// Connects to the server and performs the handshake.
+//
+// A `NULL` `socket_path` stands for the path from `DISPLAY_DISTRIBUTOR_SOCKET`.
+//
+// # Safety
+//
+// `socket_path` must be `NULL` or a valid C string, and `client` must be a valid pointer.
+dd_status dd_connect(const char *socket_path, dd_client **client);
+
+// Closes a connection that hasn't requested any displays.
+//
+// # Safety
+//
+// `client` must be `NULL` or a pointer returned by [`dd_connect`] and not freed yet.
+void dd_client_free(dd_client *client);
+
+// Leases the displays with the given connector names, all the seat displays when `count` is 0.
+//
+// The call takes over the client whether it succeeds or not,
+// since the lease owns the connection.
+//
+// # Safety
+//
+// `client` must be a pointer returned by [`dd_connect`] and not freed yet,
+// `connectors` must point to `count` valid C strings, and `lease` must be a valid pointer.
+dd_status dd_request_displays(dd_client *client,
+                              const char *const *connectors,
+                              size_t count,
+                              dd_lease_mode mode,
+                              dd_lease **lease);
+
+// The number of cards the lease spans, or 0 if `lease` is `NULL`.
+//
+// # Safety
+//
+// `lease` must be `NULL` or a pointer returned by [`dd_request_displays`] and not released yet.
+size_t dd_lease_get_card_count(const dd_lease *lease);
+
+// The lease fd of the card, or -1 if there is no such card or `lease` is `NULL`.
+//
+// The fd stays owned by the lease, `dup` it to keep it past [`dd_release`].
+//
+// # Safety
+//
+// `lease` must be `NULL` or a pointer returned by [`dd_request_displays`] and not released yet.
+int dd_lease_get_fd(const dd_lease *lease, size_t card);
+
+// Copies up to `capacity` DRM object ids of the leased connectors of the card into `handles`.
+//
+// Returns the number of the leased connectors, which may exceed `capacity`,
+// or 0 if there is no such card or `lease` is `NULL`.
+//
+// # Safety
+//
+// `lease` must be `NULL` or a pointer returned by [`dd_request_displays`] and not released yet,
+// and `handles` must have room for `capacity` ids.
+size_t dd_lease_get_connectors(const dd_lease *lease,
+                               size_t card,
+                               uint32_t *handles,
+                               size_t capacity);
+
+// Releases the lease, waits for the server to confirm it and frees the lease.
+//
+// # Safety
+//
+// `lease` must be a pointer returned by [`dd_request_displays`] and not released yet.
+dd_status dd_release(dd_lease *lease);
+
+// The message of the last failure on the calling thread, or `NULL`.
+//
+// The string stays valid until the next failure on the thread.
+const char *dd_last_error(void);
+
+#ifdef __cplusplus
+}  // extern "C"
+#endif  // __cplusplus
+
+#endif  /* DISPLAY_DISTRIBUTOR_H */
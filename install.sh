@@ -0,0 +1,23 @@
+#!/bin/sh
+# Builds and installs the C API: the shared library, the header and the pkg-config file.
+#
+# PREFIX defaults to /usr/local, DESTDIR is prepended to the installed paths when packaging.
+set -eu
+
+cd "$(dirname "$0")"
+
+prefix=${PREFIX:-/usr/local}
+destdir=${DESTDIR:-}
+version=$(sed -n 's/^version = "\(.*\)"$/\1/p' Cargo.toml | head -n 1)
+
+cargo build --release --lib
+
+sed -e "s|@prefix@|$prefix|" -e "s|@version@|$version|" \
+    display-distributor.pc.in > target/release/display-distributor.pc
+
+install -Dm755 target/release/libdisplay_distributor.so \
+    "$destdir$prefix/lib/libdisplay_distributor.so"
+install -Dm644 include/display_distributor.h \
+    "$destdir$prefix/include/display_distributor.h"
+install -Dm644 target/release/display-distributor.pc \
+    "$destdir$prefix/lib/pkgconfig/display-distributor.pc"
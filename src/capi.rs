@@ -0,0 +1,302 @@
+//! The C API of the blocking client, declared in `include/display_distributor.h`.
+//!
+//! Every fallible call returns a [`DdStatus`],
+//! and the message of the last failure on the calling thread is kept for [`dd_last_error`].
+
+use std::{
+    cell::RefCell,
+    ffi::{c_char, c_int, CStr, CString},
+    os::fd::AsRawFd,
+    ptr, slice,
+};
+
+use crate::{
+    client::{Client, ClientError, Lease},
+    DisplayId, LeaseMode,
+};
+
+/// A connection to the server that has completed the handshake.
+pub struct DdClient(Client);
+
+/// Granted displays, one lease fd per card.
+pub struct DdLease(Lease);
+
+/// The result of a call, negative on failure.
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DdStatus {
+    Ok = 0,
+    InvalidArgument = -1,
+    NoSocketPath = -2,
+    UnsupportedVersion = -3,
+    Disconnected = -4,
+    /// The server sent something the client doesn't understand.
+    Protocol = -5,
+    SeatBusy = -6,
+    NoDisplays = -7,
+    UnknownDisplay = -8,
+    DisplayUnavailable = -9,
+    AmbiguousDisplay = -10,
+    DisplayBusy = -11,
+    LeaseFailed = -12,
+    NoPermission = -13,
+    /// The server failed on its side.
+    Server = -14,
+    Io = -15,
+}
+
+impl From<&ClientError> for DdStatus {
+    fn from(err: &ClientError) -> Self {
+        match err {
+            ClientError::NoSocketPath => Self::NoSocketPath,
+            ClientError::UnsupportedVersion { .. } => Self::UnsupportedVersion,
+            ClientError::Disconnected => Self::Disconnected,
+            ClientError::UnexpectedMessage | ClientError::MissingFds(_) | ClientError::Frame(_) => {
+                Self::Protocol
+            }
+            ClientError::SeatBusy => Self::SeatBusy,
+            ClientError::NoDisplays => Self::NoDisplays,
+            ClientError::UnknownDisplay(_) => Self::UnknownDisplay,
+            ClientError::DisplayUnavailable(_) => Self::DisplayUnavailable,
+            ClientError::AmbiguousDisplay(_) => Self::AmbiguousDisplay,
+            ClientError::DisplayBusy(_) => Self::DisplayBusy,
+            ClientError::LeaseFailed { .. } => Self::LeaseFailed,
+            ClientError::NoPermission => Self::NoPermission,
+            ClientError::Server { .. } => Self::Server,
+            ClientError::Io(_) => Self::Io,
+        }
+    }
+}
+
+/// What to do when some of the requested cards can't be leased.
+#[repr(C)]
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum DdLeaseMode {
+    AllOrNothing = 0,
+    BestEffort = 1,
+}
+
+impl From<DdLeaseMode> for LeaseMode {
+    fn from(mode: DdLeaseMode) -> Self {
+        match mode {
+            DdLeaseMode::AllOrNothing => Self::AllOrNothing,
+            DdLeaseMode::BestEffort => Self::BestEffort,
+        }
+    }
+}
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = const { RefCell::new(None) };
+}
+
+fn set_last_error(message: impl ToString) {
+    let message = CString::new(message.to_string().replace('\0', " ")).unwrap_or_default();
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = Some(message));
+}
+
+fn fail(err: ClientError) -> DdStatus {
+    let status = DdStatus::from(&err);
+    set_last_error(err);
+
+    status
+}
+
+fn invalid_argument(message: &str) -> DdStatus {
+    set_last_error(message);
+
+    DdStatus::InvalidArgument
+}
+
+/// Connects to the server and performs the handshake.
+///
+/// A `NULL` `socket_path` stands for the path from `DISPLAY_DISTRIBUTOR_SOCKET`.
+///
+/// # Safety
+///
+/// `socket_path` must be `NULL` or a valid C string, and `client` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn dd_connect(
+    socket_path: *const c_char,
+    client: *mut *mut DdClient,
+) -> DdStatus {
+    if client.is_null() {
+        return invalid_argument("client must not be NULL");
+    }
+
+    let result = if socket_path.is_null() {
+        Client::connect_default()
+    } else {
+        let socket_path = CStr::from_ptr(socket_path);
+        match socket_path.to_str() {
+            Ok(socket_path) => Client::connect(socket_path),
+            Err(_) => return invalid_argument("socket_path must be UTF-8"),
+        }
+    };
+
+    match result {
+        Ok(connected) => {
+            *client = Box::into_raw(Box::new(DdClient(connected)));
+            DdStatus::Ok
+        }
+        Err(err) => fail(err),
+    }
+}
+
+/// Closes a connection that hasn't requested any displays.
+///
+/// # Safety
+///
+/// `client` must be `NULL` or a pointer returned by [`dd_connect`] and not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn dd_client_free(client: *mut DdClient) {
+    if !client.is_null() {
+        drop(Box::from_raw(client));
+    }
+}
+
+/// Leases the displays with the given connector names, all the seat displays when `count` is 0.
+///
+/// The call takes over the client whether it succeeds or not,
+/// since the lease owns the connection.
+///
+/// # Safety
+///
+/// `client` must be a pointer returned by [`dd_connect`] and not freed yet,
+/// `connectors` must point to `count` valid C strings, and `lease` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn dd_request_displays(
+    client: *mut DdClient,
+    connectors: *const *const c_char,
+    count: usize,
+    mode: DdLeaseMode,
+    lease: *mut *mut DdLease,
+) -> DdStatus {
+    if client.is_null() {
+        return invalid_argument("client must not be NULL");
+    }
+    let DdClient(client) = *Box::from_raw(client);
+
+    if lease.is_null() || (connectors.is_null() && count > 0) {
+        return invalid_argument("lease and connectors must not be NULL");
+    }
+
+    let mut displays = Vec::with_capacity(count);
+    if count > 0 {
+        for connector in slice::from_raw_parts(connectors, count) {
+            if connector.is_null() {
+                return invalid_argument("connector names must not be NULL");
+            }
+
+            match CStr::from_ptr(*connector).to_str() {
+                Ok(name) => displays.push(DisplayId::Connector(name.to_string())),
+                Err(_) => return invalid_argument("connector names must be UTF-8"),
+            }
+        }
+    }
+
+    match client.request_displays(&displays, mode.into()) {
+        Ok(granted) => {
+            *lease = Box::into_raw(Box::new(DdLease(granted)));
+            DdStatus::Ok
+        }
+        Err(err) => fail(err),
+    }
+}
+
+/// The number of cards the lease spans, or 0 if `lease` is `NULL`.
+///
+/// # Safety
+///
+/// `lease` must be `NULL` or a pointer returned by [`dd_request_displays`] and not released yet.
+#[no_mangle]
+pub unsafe extern "C" fn dd_lease_get_card_count(lease: *const DdLease) -> usize {
+    if lease.is_null() {
+        return 0;
+    }
+
+    (*lease).0.cards().len()
+}
+
+/// The lease fd of the card, or -1 if there is no such card or `lease` is `NULL`.
+///
+/// The fd stays owned by the lease, `dup` it to keep it past [`dd_release`].
+///
+/// # Safety
+///
+/// `lease` must be `NULL` or a pointer returned by [`dd_request_displays`] and not released yet.
+#[no_mangle]
+pub unsafe extern "C" fn dd_lease_get_fd(lease: *const DdLease, card: usize) -> c_int {
+    if lease.is_null() {
+        return -1;
+    }
+
+    (*lease)
+        .0
+        .cards()
+        .get(card)
+        .map_or(-1, |card| card.fd.as_raw_fd())
+}
+
+/// Copies up to `capacity` DRM object ids of the leased connectors of the card into `handles`.
+///
+/// Returns the number of the leased connectors, which may exceed `capacity`,
+/// or 0 if there is no such card or `lease` is `NULL`.
+///
+/// # Safety
+///
+/// `lease` must be `NULL` or a pointer returned by [`dd_request_displays`] and not released yet,
+/// and `handles` must have room for `capacity` ids.
+#[no_mangle]
+pub unsafe extern "C" fn dd_lease_get_connectors(
+    lease: *const DdLease,
+    card: usize,
+    handles: *mut u32,
+    capacity: usize,
+) -> usize {
+    if lease.is_null() {
+        return 0;
+    }
+
+    let Some(card) = (*lease).0.cards().get(card) else {
+        return 0;
+    };
+
+    let connectors = &card.grant.connectors;
+    if !handles.is_null() {
+        for (index, connector) in connectors.iter().take(capacity).enumerate() {
+            *handles.add(index) = connector.handle;
+        }
+    }
+
+    connectors.len()
+}
+
+/// Releases the lease, waits for the server to confirm it and frees the lease.
+///
+/// # Safety
+///
+/// `lease` must be a pointer returned by [`dd_request_displays`] and not released yet.
+#[no_mangle]
+pub unsafe extern "C" fn dd_release(lease: *mut DdLease) -> DdStatus {
+    if lease.is_null() {
+        return invalid_argument("lease must not be NULL");
+    }
+
+    match Box::from_raw(lease).0.release() {
+        Ok(()) => DdStatus::Ok,
+        Err(err) => fail(err),
+    }
+}
+
+/// The message of the last failure on the calling thread, or `NULL`.
+///
+/// The string stays valid until the next failure on the thread.
+#[no_mangle]
+pub extern "C" fn dd_last_error() -> *const c_char {
+    LAST_ERROR.with(|last_error| {
+        last_error
+            .borrow()
+            .as_ref()
+            .map_or(ptr::null(), |message| message.as_ptr())
+    })
+}
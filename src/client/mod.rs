@@ -0,0 +1,470 @@
+//! A blocking client of the display distributor.
+//!
+//! ```no_run
+//! use display_distributor::{client::Client, LeaseMode};
+//!
+//! let client = Client::connect_default()?;
+//! let lease = client.request_displays(&[], LeaseMode::AllOrNothing)?;
+//!
+//! for card in lease.cards() {
+//!     println!("{} leased on {}", card.grant.lessee_id, card.grant.card.sysname);
+//! }
+//! # Ok::<(), display_distributor::client::ClientError>(())
+//! ```
+//!
+//! The `async` feature adds the same API for the tokio runtime in [`tokio`].
+
+use std::{
+    collections::VecDeque,
+    env,
+    ffi::OsStr,
+    io,
+    os::{
+        fd::{AsFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+        unix::net::UnixStream,
+    },
+    path::{Path, PathBuf},
+};
+
+use sendfd::RecvWithFd;
+use thiserror::Error;
+
+use crate::{
+    framing::{self, FrameDecoder, FrameError},
+    Capabilities, CardInfo, ClientMessage, DisplayId, ErrorCode, LeaseGrant, LeaseMode,
+    RevokeReason, ServerMessage, PROTOCOL_VERSION,
+};
+
+#[cfg(feature = "async")]
+pub mod tokio;
+
+/// The environment variable with the path of the server socket.
+pub const SOCKET_ENV: &str = "DISPLAY_DISTRIBUTOR_SOCKET";
+
+/// The most fds a single read can carry.
+const MAX_FDS_PER_READ: usize = 16;
+
+#[derive(Error, Debug)]
+pub enum ClientError {
+    #[error("{SOCKET_ENV} is not set")]
+    NoSocketPath,
+
+    #[error("The server speaks only the protocol versions {min_version} to {max_version}")]
+    UnsupportedVersion { min_version: u32, max_version: u32 },
+
+    #[error("The server closed the connection")]
+    Disconnected,
+
+    #[error("Unexpected message from the server")]
+    UnexpectedMessage,
+
+    #[error("The server granted {0} leases but passed fewer fds")]
+    MissingFds(usize),
+
+    #[error("Seat displays are already leased")]
+    SeatBusy,
+
+    #[error("Seat has no displays")]
+    NoDisplays,
+
+    #[error("The display {0} is not found")]
+    UnknownDisplay(DisplayId),
+
+    #[error("The display {0} doesn't belong to the seat")]
+    DisplayUnavailable(DisplayId),
+
+    #[error("Several displays of the seat match {0}")]
+    AmbiguousDisplay(DisplayId),
+
+    #[error("The display {0} is already leased")]
+    DisplayBusy(DisplayId),
+
+    #[error("Unable to lease the displays of {}: {message}", .card.devnode.display())]
+    LeaseFailed {
+        card: Box<CardInfo>,
+        message: String,
+    },
+
+    #[error("The lease belongs to another process")]
+    NoPermission,
+
+    #[error("Server error ({code:?}): {message}")]
+    Server { code: ErrorCode, message: String },
+
+    #[error("Framing error: {0}")]
+    Frame(#[from] FrameError),
+
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// A message the server pushes on its own.
+#[derive(Clone, PartialEq, Eq, Debug)]
+pub enum Event {
+    LeaseRevoked(RevokeReason),
+    DisplayAdded(DisplayId),
+    DisplayRemoved(DisplayId),
+    /// The lease fds stopped working as the session is inactive, the lease has no cards until resumed.
+    LeaseSuspended,
+    /// The session is active again, the lease cards are replaced with the new lease fds.
+    LeaseResumed,
+    /// The system is about to sleep.
+    PrepareForSleep,
+    /// The system has resumed.
+    ResumedFromSleep,
+}
+
+/// A connection to the server that has completed the handshake.
+pub struct Client {
+    connection: Connection,
+    protocol_version: u32,
+    capabilities: Capabilities,
+}
+
+impl Client {
+    /// Connects to the socket from [`SOCKET_ENV`].
+    pub fn connect_default() -> Result<Self, ClientError> {
+        Self::connect(socket_path()?)
+    }
+
+    pub fn connect(path: impl AsRef<Path>) -> Result<Self, ClientError> {
+        let mut connection = Connection {
+            stream: UnixStream::connect(path)?,
+            inbox: Inbox::default(),
+        };
+
+        connection.send(&hello())?;
+        let (protocol_version, capabilities) = handshake_reply(connection.recv_reply()?)?;
+
+        Ok(Self {
+            connection,
+            protocol_version,
+            capabilities,
+        })
+    }
+
+    /// The protocol version both sides agreed on.
+    pub fn protocol_version(&self) -> u32 {
+        self.protocol_version
+    }
+
+    /// The features both sides agreed on.
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    /// Leases the displays of the client's seat.
+    ///
+    /// An empty list asks for every display of the seat.
+    /// The lease owns the connection, since closing it releases the lease.
+    pub fn request_displays(
+        mut self,
+        displays: &[DisplayId],
+        mode: LeaseMode,
+    ) -> Result<Lease, ClientError> {
+        self.connection.send(&request(displays, mode))?;
+
+        let reply = self.connection.recv_reply()?;
+        let cards = self.connection.inbox.lease_cards(reply)?;
+
+        Ok(Lease {
+            client: self,
+            cards,
+        })
+    }
+}
+
+/// The lease fd of a single card along with what it covers.
+pub struct LeasedCard {
+    pub fd: OwnedFd,
+    pub grant: LeaseGrant,
+}
+
+impl AsFd for LeasedCard {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+/// Granted displays, one lease fd per card.
+///
+/// Dropping the lease closes the connection, which releases the lease.
+pub struct Lease {
+    client: Client,
+    cards: Vec<LeasedCard>,
+}
+
+impl Lease {
+    pub fn cards(&self) -> &[LeasedCard] {
+        &self.cards
+    }
+
+    /// Waits for the next event pushed by the server.
+    ///
+    /// The server pushes events only when both sides support [`Capabilities::EVENTS`].
+    pub fn next_event(&mut self) -> Result<Event, ClientError> {
+        let connection = &mut self.client.connection;
+
+        loop {
+            if let Some(event) = connection.inbox.next_event(&mut self.cards)? {
+                return Ok(event);
+            }
+
+            connection.inbox.recv_from(&connection.stream)?;
+        }
+    }
+
+    /// Releases the lease and waits for the server to confirm it.
+    pub fn release(mut self) -> Result<(), ClientError> {
+        let connection = &mut self.client.connection;
+
+        connection.send(&ClientMessage::ReleaseDisplays)?;
+        release_reply(connection.recv_reply()?)
+    }
+}
+
+impl AsFd for Lease {
+    /// The connection socket, e.g. to wait for events in an event loop.
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.client.connection.stream.as_fd()
+    }
+}
+
+/// The socket path from [`SOCKET_ENV`].
+pub fn socket_path() -> Result<PathBuf, ClientError> {
+    env::var_os(SOCKET_ENV)
+        .filter(|path| !path.is_empty())
+        .map(PathBuf::from)
+        .ok_or(ClientError::NoSocketPath)
+}
+
+struct Connection {
+    stream: UnixStream,
+    inbox: Inbox,
+}
+
+impl Connection {
+    fn send(&mut self, message: &ClientMessage) -> Result<(), ClientError> {
+        framing::write_frame(&mut self.stream, message)?;
+
+        Ok(())
+    }
+
+    fn recv_reply(&mut self) -> Result<ServerMessage, ClientError> {
+        loop {
+            if let Some(reply) = self.inbox.next_reply()? {
+                return Ok(reply);
+            }
+
+            self.inbox.recv_from(&self.stream)?;
+        }
+    }
+}
+
+/// Everything received from the server and not yet consumed.
+///
+/// Shared by the blocking and the async clients, which only differ in how they wait for the socket.
+#[derive(Default)]
+struct Inbox {
+    decoder: FrameDecoder,
+    /// The received fds not yet claimed by a message.
+    fds: VecDeque<OwnedFd>,
+    /// The events received while waiting for a reply.
+    events: VecDeque<Event>,
+    /// The cards of the `LeaseResumed` events not yet taken.
+    resumed: VecDeque<Vec<LeasedCard>>,
+}
+
+impl Inbox {
+    /// Receives a chunk of bytes along with the fds attached to them.
+    ///
+    /// The socket decides whether to block, a non-blocking one fails with `WouldBlock`.
+    fn recv_from(&mut self, stream: &impl RecvWithFd) -> Result<(), ClientError> {
+        let mut buffer = [0; 4096];
+        let mut fds: [RawFd; MAX_FDS_PER_READ] = [-1; MAX_FDS_PER_READ];
+        let (size, fd_count) = stream.recv_with_fd(&mut buffer, &mut fds)?;
+
+        // SAFETY: the fds are freshly received and are owned by nobody else.
+        self.fds.extend(
+            fds[..fd_count]
+                .iter()
+                .map(|fd| unsafe { OwnedFd::from_raw_fd(*fd) }),
+        );
+
+        if size == 0 {
+            return Err(ClientError::Disconnected);
+        }
+
+        self.decoder.push(&buffer[..size]);
+
+        Ok(())
+    }
+
+    /// Takes the next reply, keeping the events that arrived before it for later.
+    fn next_reply(&mut self) -> Result<Option<ServerMessage>, ClientError> {
+        while let Some(message) = self.decoder.next_message()? {
+            match self.classify(message)? {
+                Incoming::Event(event) => self.events.push_back(event),
+                Incoming::Reply(reply) => return Ok(Some(reply)),
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Takes the next event, keeping the lease `cards` in line with a suspension or a resumption.
+    fn next_event(&mut self, cards: &mut Vec<LeasedCard>) -> Result<Option<Event>, ClientError> {
+        let mut event = self.events.pop_front();
+
+        while event.is_none() {
+            let Some(message) = self.decoder.next_message()? else {
+                return Ok(None);
+            };
+
+            if let Incoming::Event(message_event) = self.classify(message)? {
+                event = Some(message_event);
+            }
+        }
+
+        match event {
+            Some(Event::LeaseSuspended) => cards.clear(),
+            Some(Event::LeaseResumed) => *cards = self.resumed.pop_front().unwrap_or_default(),
+            _ => {}
+        }
+
+        Ok(event)
+    }
+
+    /// Tells the events apart from the replies, claiming the fds of a resumed lease.
+    fn classify(&mut self, message: ServerMessage) -> Result<Incoming, ClientError> {
+        if let ServerMessage::LeaseResumed { leases } = message {
+            let cards = self.claim_fds(leases)?;
+            self.resumed.push_back(cards);
+
+            return Ok(Incoming::Event(Event::LeaseResumed));
+        }
+
+        Ok(classify(message))
+    }
+
+    fn lease_cards(&mut self, reply: ServerMessage) -> Result<Vec<LeasedCard>, ClientError> {
+        let ServerMessage::LeaseGranted { leases } = reply else {
+            return Err(reply_error(reply));
+        };
+
+        self.claim_fds(leases)
+    }
+
+    /// Pairs the granted leases with the fds that came along.
+    fn claim_fds(&mut self, leases: Vec<LeaseGrant>) -> Result<Vec<LeasedCard>, ClientError> {
+        let lease_count = leases.len();
+        if self.fds.len() < lease_count {
+            return Err(ClientError::MissingFds(lease_count));
+        }
+
+        Ok(leases
+            .into_iter()
+            .zip(self.fds.drain(..lease_count))
+            .map(|(grant, fd)| LeasedCard { fd, grant })
+            .collect())
+    }
+}
+
+enum Incoming {
+    Event(Event),
+    Reply(ServerMessage),
+}
+
+/// Tells the events pushed by the server apart from the replies.
+fn classify(message: ServerMessage) -> Incoming {
+    match message {
+        ServerMessage::LeaseRevoked { reason } if reason != RevokeReason::Released => {
+            Incoming::Event(Event::LeaseRevoked(reason))
+        }
+        ServerMessage::DisplayAdded(display) => Incoming::Event(Event::DisplayAdded(display)),
+        ServerMessage::DisplayRemoved(display) => Incoming::Event(Event::DisplayRemoved(display)),
+        ServerMessage::LeaseSuspended => Incoming::Event(Event::LeaseSuspended),
+        ServerMessage::PrepareForSleep => Incoming::Event(Event::PrepareForSleep),
+        ServerMessage::ResumedFromSleep => Incoming::Event(Event::ResumedFromSleep),
+        message => Incoming::Reply(message),
+    }
+}
+
+fn hello() -> ClientMessage {
+    ClientMessage::Hello {
+        protocol_version: PROTOCOL_VERSION,
+        client_name: client_name(),
+        capabilities: Capabilities::SUPPORTED,
+    }
+}
+
+fn request(displays: &[DisplayId], mode: LeaseMode) -> ClientMessage {
+    ClientMessage::RequestDisplays {
+        displays: displays.to_vec(),
+        mode,
+    }
+}
+
+/// The protocol version and the agreed capabilities.
+///
+/// A server of another version speaks other messages, so it is refused as well.
+fn handshake_reply(reply: ServerMessage) -> Result<(u32, Capabilities), ClientError> {
+    match reply {
+        ServerMessage::Hello {
+            protocol_version,
+            capabilities,
+        } if protocol_version == PROTOCOL_VERSION => Ok((protocol_version, capabilities)),
+        ServerMessage::Hello {
+            protocol_version, ..
+        } => Err(ClientError::UnsupportedVersion {
+            min_version: protocol_version,
+            max_version: protocol_version,
+        }),
+        ServerMessage::UnsupportedVersion {
+            min_version,
+            max_version,
+        } => Err(ClientError::UnsupportedVersion {
+            min_version,
+            max_version,
+        }),
+        reply => Err(reply_error(reply)),
+    }
+}
+
+fn release_reply(reply: ServerMessage) -> Result<(), ClientError> {
+    match reply {
+        ServerMessage::LeaseRevoked { .. } | ServerMessage::LeaseNotFound => Ok(()),
+        reply => Err(reply_error(reply)),
+    }
+}
+
+/// Turns a refusal of the server into an error.
+fn reply_error(message: ServerMessage) -> ClientError {
+    match message {
+        ServerMessage::SeatBusy => ClientError::SeatBusy,
+        ServerMessage::NoDisplays => ClientError::NoDisplays,
+        ServerMessage::UnknownDisplay(display) => ClientError::UnknownDisplay(display),
+        ServerMessage::DisplayUnavailable(display) => ClientError::DisplayUnavailable(display),
+        ServerMessage::AmbiguousDisplay(display) => ClientError::AmbiguousDisplay(display),
+        ServerMessage::DisplayBusy(display) => ClientError::DisplayBusy(display),
+        ServerMessage::LeaseFailed { card, message } => ClientError::LeaseFailed {
+            card: Box::new(card),
+            message,
+        },
+        ServerMessage::NoPermission => ClientError::NoPermission,
+        ServerMessage::Error { code, message } => ClientError::Server { code, message },
+        _ => ClientError::UnexpectedMessage,
+    }
+}
+
+/// The name the client introduces itself with, the executable name.
+fn client_name() -> String {
+    env::args_os()
+        .next()
+        .as_deref()
+        .map(Path::new)
+        .and_then(Path::file_name)
+        .map(OsStr::to_string_lossy)
+        .map(|name| name.to_string())
+        .unwrap_or_default()
+}
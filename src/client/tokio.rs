@@ -0,0 +1,195 @@
+//! The client for the tokio runtime, the same as the blocking one but with async calls.
+//!
+//! ```no_run
+//! use display_distributor::{client::tokio::Client, LeaseMode};
+//!
+//! # async fn example() -> Result<(), display_distributor::client::ClientError> {
+//! let client = Client::connect_default().await?;
+//! let mut lease = client.request_displays(&[], LeaseMode::AllOrNothing).await?;
+//!
+//! let event = lease.next_event().await?;
+//! println!("{event:?}");
+//!
+//! lease.release().await
+//! # }
+//! ```
+
+use std::{
+    future, io,
+    os::fd::{AsFd, BorrowedFd},
+    path::Path,
+    pin::Pin,
+    task::{ready, Context, Poll},
+};
+
+use ::tokio::{io::AsyncWriteExt, net::UnixStream};
+use futures_core::Stream;
+
+use crate::{framing, Capabilities, ClientMessage, DisplayId, LeaseMode, ServerMessage};
+
+use super::{
+    handshake_reply, hello, release_reply, request, socket_path, ClientError, Event, Inbox,
+    LeasedCard,
+};
+
+/// A connection to the server that has completed the handshake.
+pub struct Client {
+    connection: Connection,
+    protocol_version: u32,
+    capabilities: Capabilities,
+}
+
+impl Client {
+    /// Connects to the socket from [`SOCKET_ENV`](super::SOCKET_ENV).
+    pub async fn connect_default() -> Result<Self, ClientError> {
+        Self::connect(socket_path()?).await
+    }
+
+    pub async fn connect(path: impl AsRef<Path>) -> Result<Self, ClientError> {
+        let mut connection = Connection {
+            stream: UnixStream::connect(path).await?,
+            inbox: Inbox::default(),
+        };
+
+        connection.send(&hello()).await?;
+        let (protocol_version, capabilities) = handshake_reply(connection.recv_reply().await?)?;
+
+        Ok(Self {
+            connection,
+            protocol_version,
+            capabilities,
+        })
+    }
+
+    /// The protocol version both sides agreed on.
+    pub fn protocol_version(&self) -> u32 {
+        self.protocol_version
+    }
+
+    /// The features both sides agreed on.
+    pub fn capabilities(&self) -> Capabilities {
+        self.capabilities
+    }
+
+    /// Leases the displays of the client's seat.
+    ///
+    /// An empty list asks for every display of the seat.
+    /// The lease owns the connection, since closing it releases the lease.
+    pub async fn request_displays(
+        mut self,
+        displays: &[DisplayId],
+        mode: LeaseMode,
+    ) -> Result<Lease, ClientError> {
+        self.connection.send(&request(displays, mode)).await?;
+
+        let reply = self.connection.recv_reply().await?;
+        let cards = self.connection.inbox.lease_cards(reply)?;
+
+        Ok(Lease {
+            client: self,
+            cards,
+        })
+    }
+}
+
+/// Granted displays, one lease fd per card.
+///
+/// The lease is also a [`Stream`] of the server events, which ends once the server closes the connection.
+/// Dropping the lease closes the connection, which releases the lease.
+pub struct Lease {
+    client: Client,
+    cards: Vec<LeasedCard>,
+}
+
+impl Lease {
+    pub fn cards(&self) -> &[LeasedCard] {
+        &self.cards
+    }
+
+    /// Waits for the next event pushed by the server.
+    pub async fn next_event(&mut self) -> Result<Event, ClientError> {
+        let connection = &mut self.client.connection;
+
+        future::poll_fn(|cx| connection.poll_event(cx, &mut self.cards)).await
+    }
+
+    /// Releases the lease and waits for the server to confirm it.
+    pub async fn release(mut self) -> Result<(), ClientError> {
+        let connection = &mut self.client.connection;
+
+        connection.send(&ClientMessage::ReleaseDisplays).await?;
+        release_reply(connection.recv_reply().await?)
+    }
+}
+
+impl Stream for Lease {
+    type Item = Result<Event, ClientError>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let lease = self.get_mut();
+
+        match ready!(lease.client.connection.poll_event(cx, &mut lease.cards)) {
+            Ok(event) => Poll::Ready(Some(Ok(event))),
+            Err(ClientError::Disconnected) => Poll::Ready(None),
+            Err(err) => Poll::Ready(Some(Err(err))),
+        }
+    }
+}
+
+impl AsFd for Lease {
+    /// The connection socket.
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.client.connection.stream.as_fd()
+    }
+}
+
+struct Connection {
+    stream: UnixStream,
+    inbox: Inbox,
+}
+
+impl Connection {
+    async fn send(&mut self, message: &ClientMessage) -> Result<(), ClientError> {
+        let frame = framing::encode(message)?;
+        self.stream.write_all(&frame).await?;
+
+        Ok(())
+    }
+
+    async fn recv_reply(&mut self) -> Result<ServerMessage, ClientError> {
+        loop {
+            if let Some(reply) = self.inbox.next_reply()? {
+                return Ok(reply);
+            }
+
+            future::poll_fn(|cx| self.poll_recv(cx)).await?;
+        }
+    }
+
+    fn poll_event(
+        &mut self,
+        cx: &mut Context<'_>,
+        cards: &mut Vec<LeasedCard>,
+    ) -> Poll<Result<Event, ClientError>> {
+        loop {
+            if let Some(event) = self.inbox.next_event(cards)? {
+                return Poll::Ready(Ok(event));
+            }
+
+            ready!(self.poll_recv(cx))?;
+        }
+    }
+
+    /// Receives a chunk once the socket becomes readable.
+    fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), ClientError>> {
+        loop {
+            ready!(self.stream.poll_read_ready(cx))?;
+
+            // The readiness may be stale, then it gets cleared and the socket is polled again.
+            match self.inbox.recv_from(&self.stream) {
+                Err(ClientError::Io(err)) if err.kind() == io::ErrorKind::WouldBlock => continue,
+                result => return Poll::Ready(result),
+            }
+        }
+    }
+}
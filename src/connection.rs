@@ -0,0 +1,239 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+    os::{
+        fd::{AsRawFd, OwnedFd, RawFd},
+        unix::net::UnixStream,
+    },
+    time::{Duration, Instant},
+};
+
+use display_distributor::{framing::FrameDecoder, Capabilities, ClientMessage};
+use libc::pid_t;
+use nix::poll::PollFlags;
+use sendfd::SendWithFd;
+
+use crate::{dbus::Session, distributor::SeatId, peer::Peer, Error};
+
+pub type ClientId = u64;
+
+/// How long a client may take to send the rest of a started message.
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// How long a client may leave the server replies unread.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[derive(Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ConnectionState {
+    /// Waiting for the client `Hello`.
+    Handshake,
+
+    /// Serving requests and pushing events.
+    Ready,
+
+    /// The last reply is queued, the connection gets closed once it is sent.
+    Closing,
+}
+
+struct OutgoingFrame {
+    bytes: Vec<u8>,
+    sent: usize,
+    /// Passed along with the first chunk of the frame.
+    fds: Vec<OwnedFd>,
+}
+
+/// A non-blocking connection of a client.
+///
+/// The incoming bytes are buffered until a whole message arrives,
+/// and the outgoing messages are queued until the socket accepts them,
+/// so a slow client never blocks the server.
+pub struct ClientConnection {
+    id: ClientId,
+    stream: UnixStream,
+    peer: Peer,
+    session: Session,
+    state: ConnectionState,
+    capabilities: Capabilities,
+    decoder: FrameDecoder,
+    outgoing: VecDeque<OutgoingFrame>,
+    last_read: Instant,
+    last_write: Instant,
+}
+
+impl ClientConnection {
+    pub fn new(
+        id: ClientId,
+        stream: UnixStream,
+        peer: Peer,
+        session: Session,
+    ) -> Result<Self, Error> {
+        stream.set_nonblocking(true)?;
+
+        let now = Instant::now();
+        Ok(Self {
+            id,
+            stream,
+            peer,
+            session,
+            state: ConnectionState::Handshake,
+            capabilities: Capabilities::NONE,
+            decoder: FrameDecoder::new(),
+            outgoing: VecDeque::new(),
+            last_read: now,
+            last_write: now,
+        })
+    }
+
+    pub fn id(&self) -> ClientId {
+        self.id
+    }
+
+    pub fn peer(&self) -> &Peer {
+        &self.peer
+    }
+
+    pub fn pid(&self) -> pid_t {
+        self.peer.pid()
+    }
+
+    pub fn session(&self) -> &Session {
+        &self.session
+    }
+
+    pub fn seat(&self) -> &SeatId {
+        &self.session.seat
+    }
+
+    pub fn state(&self) -> ConnectionState {
+        self.state
+    }
+
+    pub fn set_state(&mut self, state: ConnectionState) {
+        self.state = state;
+    }
+
+    /// The features both sides agreed on during the handshake.
+    pub fn set_capabilities(&mut self, capabilities: Capabilities) {
+        self.capabilities = capabilities;
+    }
+
+    /// Whether the server can push events to the client.
+    pub fn accepts_events(&self) -> bool {
+        self.state == ConnectionState::Ready && self.capabilities.contains(Capabilities::EVENTS)
+    }
+
+    /// Whether the client can follow its lease being suspended and resumed.
+    pub fn accepts_suspend(&self) -> bool {
+        self.accepts_events() && self.capabilities.contains(Capabilities::SUSPEND)
+    }
+
+    /// Whether the client can be told about the system sleep.
+    pub fn accepts_sleep(&self) -> bool {
+        self.accepts_events() && self.capabilities.contains(Capabilities::SLEEP)
+    }
+
+    /// The events to poll the connection for.
+    pub fn poll_flags(&self) -> PollFlags {
+        if self.outgoing.is_empty() {
+            PollFlags::POLLIN
+        } else {
+            PollFlags::POLLIN | PollFlags::POLLOUT
+        }
+    }
+
+    /// The time the client gets dropped at unless it makes progress.
+    pub fn deadline(&self) -> Option<Instant> {
+        if !self.outgoing.is_empty() {
+            return Some(self.last_write + WRITE_TIMEOUT);
+        }
+
+        // An idle client may keep the connection open for as long as it likes.
+        match self.state {
+            ConnectionState::Handshake => Some(self.last_read + READ_TIMEOUT),
+            ConnectionState::Ready if !self.decoder.is_empty() => {
+                Some(self.last_read + READ_TIMEOUT)
+            }
+            ConnectionState::Ready | ConnectionState::Closing => None,
+        }
+    }
+
+    /// Whether the connection has nothing more to do.
+    pub fn is_finished(&self) -> bool {
+        self.state == ConnectionState::Closing && self.outgoing.is_empty()
+    }
+
+    /// Buffers what the socket has to read, up to the largest frame.
+    ///
+    /// The rest is read once the buffered messages are taken.
+    /// Returns `false` once the client has closed its end.
+    pub fn read(&mut self) -> Result<bool, Error> {
+        let mut buffer = [0; 4096];
+
+        while !self.decoder.is_full() {
+            match self.stream.read(&mut buffer) {
+                Ok(0) => return Ok(false),
+                Ok(size) => {
+                    self.decoder.push(&buffer[..size]);
+                    self.last_read = Instant::now();
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(true),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok(true)
+    }
+
+    pub fn next_message(&mut self) -> Result<Option<ClientMessage>, Error> {
+        Ok(self.decoder.next_message()?)
+    }
+
+    pub fn queue_frame(&mut self, bytes: Vec<u8>, fds: Vec<OwnedFd>) {
+        if self.outgoing.is_empty() {
+            self.last_write = Instant::now();
+        }
+
+        self.outgoing.push_back(OutgoingFrame {
+            bytes,
+            sent: 0,
+            fds,
+        });
+    }
+
+    /// Writes as much of the queued frames as the socket accepts.
+    pub fn flush(&mut self) -> Result<(), Error> {
+        while let Some(frame) = self.outgoing.front_mut() {
+            let result = if frame.fds.is_empty() {
+                self.stream.write(&frame.bytes[frame.sent..])
+            } else {
+                let fds: Vec<RawFd> = frame.fds.iter().map(AsRawFd::as_raw_fd).collect();
+                self.stream.send_with_fd(&frame.bytes[frame.sent..], &fds)
+            };
+
+            match result {
+                Ok(sent) => {
+                    frame.sent += sent;
+                    // The client has its own copies of the fds now.
+                    frame.fds.clear();
+                    self.last_write = Instant::now();
+
+                    if frame.sent == frame.bytes.len() {
+                        self.outgoing.pop_front();
+                    }
+                }
+                Err(err) if err.kind() == io::ErrorKind::WouldBlock => return Ok(()),
+                Err(err) if err.kind() == io::ErrorKind::Interrupted => continue,
+                Err(err) => return Err(err.into()),
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl AsRawFd for ClientConnection {
+    fn as_raw_fd(&self) -> RawFd {
+        self.stream.as_raw_fd()
+    }
+}
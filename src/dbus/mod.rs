@@ -1,7 +1,12 @@
-use std::time::Duration;
+use std::{sync::mpsc::Sender, time::Duration};
 
 use crate::{distributor::SeatId, Error};
-use dbus::blocking::Connection;
+use dbus::{
+    arg::prop_cast,
+    blocking::{stdintf::org_freedesktop_dbus::PropertiesPropertiesChanged, Connection},
+    message::{MatchRule, SignalArgs},
+    Message,
+};
 use log::trace;
 
 pub mod login1 {
@@ -12,29 +17,258 @@ pub mod login1 {
 use login1::manager::*;
 use login1::session::*;
 
-pub trait ProcessSeat {
-    fn process_seat(&self, pid: u32) -> Result<SeatId, Error>;
+const LOGIN1_SERVICE: &str = "org.freedesktop.login1";
+const LOGIN1_PATH: &str = "/org/freedesktop/login1";
+const LOGIN1_SESSIONS_PATH: &str = "/org/freedesktop/login1/session";
+const LOGIN1_SESSION_INTERFACE: &str = "org.freedesktop.login1.Session";
+const TIMEOUT: Duration = Duration::from_secs(5);
+
+/// The DBus object path of a logind session.
+pub type SessionPath = dbus::Path<'static>;
+
+/// The logind session a process belongs to.
+#[derive(Clone, Debug)]
+pub struct Session {
+    pub path: SessionPath,
+    pub seat: SeatId,
 }
 
-impl ProcessSeat for Connection {
+/// The session properties deciding whether the session may hold a lease.
+#[derive(Clone, Debug)]
+pub struct SessionState {
+    /// Whether the session is the one in the foreground of its seat.
+    pub active: bool,
+    /// E.g. `user` or `greeter`.
+    pub class: String,
+    pub remote: bool,
+}
+
+/// A logind inhibitor lock, held until the fd is closed.
+pub type Inhibitor = dbus::arg::OwnedFd;
+
+/// A logind signal the distributor reacts to.
+///
+/// The signal handlers only queue the events,
+/// the main loop handles them once the DBus messages are processed.
+#[derive(Debug)]
+pub enum LogindEvent {
+    SeatNew(SeatId),
+    SeatRemoved(SeatId),
+    SessionRemoved(SessionPath),
+    SessionActiveChanged {
+        session: SessionPath,
+        active: bool,
+    },
+    /// `true` before the system goes to sleep, `false` once it has resumed.
+    PrepareForSleep(bool),
+    /// The system is about to shut down.
+    PrepareForShutdown,
+}
+
+pub trait Dispatch {
+    /// Hands a received message over to its handler without waiting for one.
+    ///
+    /// Returns `false` once nothing is left to handle.
+    fn dispatch(&self) -> Result<bool, Error>;
+}
+
+impl Dispatch for Connection {
+    fn dispatch(&self) -> Result<bool, Error> {
+        self.process(Duration::ZERO).map_err(|_| Error::DBusLost)
+    }
+}
+
+pub trait Seats {
+    fn list_seats(&self) -> Result<Vec<SeatId>, Error>;
+
+    /// Queues `SeatNew` and `SeatRemoved` into `events`.
+    fn watch_seats(&self, events: Sender<LogindEvent>) -> Result<(), Error>;
+}
+
+impl Seats for Connection {
+    fn list_seats(&self) -> Result<Vec<SeatId>, Error> {
+        let session_manager = self.with_proxy(LOGIN1_SERVICE, LOGIN1_PATH, TIMEOUT);
+        let seats = OrgFreedesktopLogin1Manager::list_seats(&session_manager)?;
+
+        Ok(seats.into_iter().map(|(seat_id, _)| seat_id).collect())
+    }
+
+    fn watch_seats(&self, events: Sender<LogindEvent>) -> Result<(), Error> {
+        let session_manager = self.with_proxy(LOGIN1_SERVICE, LOGIN1_PATH, TIMEOUT);
+
+        let new_events = events.clone();
+        session_manager.match_signal(
+            move |signal: OrgFreedesktopLogin1ManagerSeatNew, _: &Connection, _: &Message| {
+                new_events
+                    .send(LogindEvent::SeatNew(signal.seat_id))
+                    .is_ok()
+            },
+        )?;
+
+        session_manager.match_signal(
+            move |signal: OrgFreedesktopLogin1ManagerSeatRemoved, _: &Connection, _: &Message| {
+                events
+                    .send(LogindEvent::SeatRemoved(signal.seat_id))
+                    .is_ok()
+            },
+        )?;
+
+        Ok(())
+    }
+}
+
+pub trait Sessions {
+    fn session_state(&self, session: &SessionPath) -> Result<SessionState, Error>;
+
+    /// Queues `SessionRemoved` and `SessionActiveChanged` into `events`.
+    fn watch_sessions(&self, events: Sender<LogindEvent>) -> Result<(), Error>;
+}
+
+impl Sessions for Connection {
+    fn session_state(&self, session: &SessionPath) -> Result<SessionState, Error> {
+        let session = self.with_proxy(LOGIN1_SERVICE, session, TIMEOUT);
+
+        Ok(SessionState {
+            active: session.active()?,
+            class: session.class()?,
+            remote: session.remote()?,
+        })
+    }
+
+    fn watch_sessions(&self, events: Sender<LogindEvent>) -> Result<(), Error> {
+        let session_manager = self.with_proxy(LOGIN1_SERVICE, LOGIN1_PATH, TIMEOUT);
+
+        let removed_events = events.clone();
+        session_manager.match_signal(
+            move |signal: OrgFreedesktopLogin1ManagerSessionRemoved,
+                  _: &Connection,
+                  _: &Message| {
+                removed_events
+                    .send(LogindEvent::SessionRemoved(signal.object_path))
+                    .is_ok()
+            },
+        )?;
+
+        // The sessions are separate objects, so every object under the sessions path is matched.
+        let properties_changed = MatchRule::new_signal(
+            PropertiesPropertiesChanged::INTERFACE,
+            PropertiesPropertiesChanged::NAME,
+        )
+        .with_sender(LOGIN1_SERVICE)
+        .with_namespaced_path(LOGIN1_SESSIONS_PATH);
+
+        self.add_match(
+            properties_changed,
+            move |signal: PropertiesPropertiesChanged, _: &Connection, message: &Message| {
+                if signal.interface_name != LOGIN1_SESSION_INTERFACE {
+                    return true;
+                }
+
+                let (Some(session), Some(active)) = (
+                    message.path(),
+                    prop_cast::<bool>(&signal.changed_properties, "Active"),
+                ) else {
+                    return true;
+                };
+
+                events
+                    .send(LogindEvent::SessionActiveChanged {
+                        session: session.into_static(),
+                        active: *active,
+                    })
+                    .is_ok()
+            },
+        )?;
+
+        Ok(())
+    }
+}
+
+pub trait Sleep {
+    /// Takes a delay lock, so the system waits for it to be released before going to sleep.
+    fn inhibit_sleep(&self) -> Result<Inhibitor, Error>;
+
+    /// Queues `PrepareForSleep` into `events`.
+    fn watch_sleep(&self, events: Sender<LogindEvent>) -> Result<(), Error>;
+}
+
+impl Sleep for Connection {
+    fn inhibit_sleep(&self) -> Result<Inhibitor, Error> {
+        let session_manager = self.with_proxy(LOGIN1_SERVICE, LOGIN1_PATH, TIMEOUT);
+
+        Ok(session_manager.inhibit(
+            "sleep",
+            env!("CARGO_PKG_NAME"),
+            "Notifying the lessees before sleep",
+            "delay",
+        )?)
+    }
+
+    fn watch_sleep(&self, events: Sender<LogindEvent>) -> Result<(), Error> {
+        let session_manager = self.with_proxy(LOGIN1_SERVICE, LOGIN1_PATH, TIMEOUT);
+
+        session_manager.match_signal(
+            move |signal: OrgFreedesktopLogin1ManagerPrepareForSleep,
+                  _: &Connection,
+                  _: &Message| {
+                events
+                    .send(LogindEvent::PrepareForSleep(signal.start))
+                    .is_ok()
+            },
+        )?;
+
+        Ok(())
+    }
+}
+
+pub trait Shutdown {
+    /// Queues `PrepareForShutdown` into `events`.
+    fn watch_shutdown(&self, events: Sender<LogindEvent>) -> Result<(), Error>;
+}
+
+impl Shutdown for Connection {
+    fn watch_shutdown(&self, events: Sender<LogindEvent>) -> Result<(), Error> {
+        let session_manager = self.with_proxy(LOGIN1_SERVICE, LOGIN1_PATH, TIMEOUT);
+
+        session_manager.match_signal(
+            move |signal: OrgFreedesktopLogin1ManagerPrepareForShutdown,
+                  _: &Connection,
+                  _: &Message| {
+                // The shutdown can be cancelled, which is signaled with `false`.
+                !signal.start || events.send(LogindEvent::PrepareForShutdown).is_ok()
+            },
+        )?;
+
+        Ok(())
+    }
+}
+
+pub trait ProcessSeat {
+    fn process_session(&self, pid: u32) -> Result<Session, Error>;
+
     fn process_seat(&self, pid: u32) -> Result<SeatId, Error> {
-        let freedesktop_service = "org.freedesktop.login1";
+        Ok(self.process_session(pid)?.seat)
+    }
+}
 
-        let timeout = Duration::from_secs(5);
-        let session_manager =
-            self.with_proxy(freedesktop_service, "/org/freedesktop/login1", timeout);
+impl ProcessSeat for Connection {
+    fn process_session(&self, pid: u32) -> Result<Session, Error> {
+        let session_manager = self.with_proxy(LOGIN1_SERVICE, LOGIN1_PATH, TIMEOUT);
 
         trace!("Acquiring session DBus path");
         let session_path = session_manager.get_session_by_pid(pid)?;
         trace!("Session path: {session_path}");
 
-        let session = self.with_proxy(freedesktop_service, session_path, timeout);
+        let session = self.with_proxy(LOGIN1_SERVICE, &session_path, TIMEOUT);
 
         let (seat_id, _) = session.seat()?;
         if seat_id.is_empty() {
             return Err(Error::NoSeat);
         }
 
-        Ok(seat_id.into())
+        Ok(Session {
+            path: session_path,
+            seat: seat_id,
+        })
     }
 }
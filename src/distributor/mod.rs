@@ -0,0 +1,2180 @@
+use std::{
+    collections::{hash_map::Entry, BTreeMap, HashMap, HashSet},
+    env, fs,
+    io::ErrorKind,
+    iter,
+    os::{
+        fd::{AsFd, AsRawFd, BorrowedFd, OwnedFd, RawFd},
+        unix::{
+            net::{UnixListener, UnixStream},
+            prelude::OsStrExt,
+        },
+    },
+    path::{Path, PathBuf},
+    sync::mpsc::{self, Receiver},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    connection::{ClientConnection, ClientId, ConnectionState},
+    dbus::{
+        Dispatch, Inhibitor, LogindEvent, ProcessSeat, Seats, Session, SessionPath, Sessions,
+        Shutdown, Sleep,
+    },
+    drm::{object_id, Card, DisplayId, DisplaysLease, PlanePolicy},
+    peer::Peer,
+    Error,
+};
+use dbus::{
+    blocking::Connection,
+    channel::{BusType, Channel},
+};
+use display_distributor::{
+    framing, version_refusal, Capabilities, CardInfo, ClientMessage, LeaseGrant, LeaseMode,
+    LeasedConnector, RevokeReason, ServerMessage, PROTOCOL_VERSION,
+};
+use drm::control::lease::LesseeId;
+use libc::{c_int, pid_t};
+use log::{error, info, warn};
+use nix::{
+    errno::Errno,
+    poll::{poll, PollFd, PollFlags},
+    sys::{
+        signal::{SigSet, Signal},
+        signalfd::{SfdFlags, SignalFd},
+    },
+};
+use udev::{Device, Enumerator, Event, EventType, MonitorBuilder, MonitorSocket};
+
+mod policy;
+#[cfg(feature = "wayland")]
+mod wayland;
+
+pub use policy::{parse_seat_policy, InactivePolicy, SessionPolicy};
+
+pub type SeatId = String;
+
+/// The seat of the devices that aren't explicitly assigned to any seat.
+pub const DEFAULT_SEAT: &str = "seat0";
+
+/// How long the lessees have to release their leases or save their state before the sleep.
+const SLEEP_DELAY: Duration = Duration::from_secs(1);
+
+pub struct Distributor {
+    dbus: Connection,
+    logind_events: Receiver<LogindEvent>,
+    /// The seats whose displays are distributed.
+    seats: HashSet<SeatId>,
+    monitor: MonitorSocket,
+    plane_policy: PlanePolicy,
+    session_policy: SessionPolicy,
+    cards: HashMap<PathBuf, Card>,
+    leases: LeaseRegistry,
+    clients: HashMap<ClientId, ClientConnection>,
+    next_client_id: ClientId,
+    /// Delays the sleep until the lessees are notified.
+    sleep_inhibitor: Option<Inhibitor>,
+    /// When the lessees run out of time to get ready for the sleep.
+    sleep_deadline: Option<Instant>,
+    /// The system is shutting down, the main loop stops at the next iteration.
+    shutdown_requested: bool,
+    #[cfg(feature = "wayland")]
+    wayland: Option<wayland::WaylandBridge>,
+}
+
+/// What a polled fd belongs to.
+enum PollSource {
+    Listener,
+    Monitor,
+    Signals,
+    DBus,
+    Holder(SeatId),
+    Client(ClientId),
+    #[cfg(feature = "wayland")]
+    WaylandSocket,
+    #[cfg(feature = "wayland")]
+    WaylandClients,
+}
+
+/// The displays to lease, grouped by the card they belong to.
+///
+/// Ordered by the card node, so the lease fds always come in the same order.
+type DisplaySelection = BTreeMap<PathBuf, HashSet<DisplayId>>;
+
+struct LeaseInfo {
+    card_node: PathBuf,
+    lessee_id: LesseeId,
+    displays: HashSet<DisplayId>,
+    grant: LeaseGrant,
+}
+
+struct Lease {
+    holder: Peer,
+    /// The logind session of the holder, the lease ends along with it.
+    session: Session,
+    /// The connection the lease was granted over, or the id of a Wayland lease object.
+    client_id: ClientId,
+    lease_fds: Vec<OwnedFd>,
+    infos: Vec<LeaseInfo>,
+    /// The DRM leases are revoked while the session is inactive, the displays stay reserved.
+    suspended: bool,
+}
+
+impl Lease {
+    fn new(holder: Peer, session: Session, client_id: ClientId) -> Self {
+        Self {
+            holder,
+            session,
+            client_id,
+            lease_fds: vec![],
+            infos: vec![],
+            suspended: false,
+        }
+    }
+
+    fn add_displays(
+        &mut self,
+        card_node: PathBuf,
+        card_info: CardInfo,
+        displays: HashSet<DisplayId>,
+        displays_lease: DisplaysLease,
+    ) {
+        let DisplaysLease {
+            fd,
+            lessee_id,
+            connectors,
+            crtcs,
+            planes,
+        } = displays_lease;
+
+        let grant = LeaseGrant {
+            card: card_info,
+            lessee_id: u32::from(lessee_id),
+            connectors: connectors
+                .into_iter()
+                .map(|(handle, display)| LeasedConnector {
+                    handle: object_id(handle),
+                    name: display.to_string(),
+                })
+                .collect(),
+            crtcs: crtcs.into_iter().map(object_id).collect(),
+            planes: planes.into_iter().map(object_id).collect(),
+        };
+
+        self.lease_fds.push(fd);
+        self.infos.push(LeaseInfo {
+            card_node,
+            lessee_id,
+            displays,
+            grant,
+        });
+    }
+
+    fn grants(&self) -> Vec<LeaseGrant> {
+        self.infos.iter().map(|info| info.grant.clone()).collect()
+    }
+
+    fn fds(&self) -> Vec<BorrowedFd<'_>> {
+        self.lease_fds.iter().map(AsFd::as_fd).collect()
+    }
+
+    /// The displays of the lease, to lease them again.
+    fn selection(&self) -> DisplaySelection {
+        self.infos
+            .iter()
+            .map(|info| (info.card_node.clone(), info.displays.clone()))
+            .collect()
+    }
+
+    /// Closes the lease fds, the DRM leases must be revoked already.
+    fn suspend(&mut self) {
+        self.lease_fds.clear();
+        self.suspended = true;
+    }
+
+    fn contains(&self, card_node: &Path, display: &DisplayId) -> bool {
+        self.infos
+            .iter()
+            .any(|info| info.card_node == card_node && info.displays.contains(display))
+    }
+}
+
+/// Every lease granted by the distributor, grouped by seat.
+///
+/// The registry owns the lease fds: they stay open for as long as the lease
+/// is registered and get closed once the lease is removed from it.
+#[derive(Default)]
+struct LeaseRegistry {
+    leases: HashMap<SeatId, Vec<Lease>>,
+}
+
+impl LeaseRegistry {
+    fn register(&mut self, seat: SeatId, lease: Lease) {
+        self.leases.entry(seat).or_default().push(lease);
+    }
+
+    fn has_leases(&self, seat: &SeatId) -> bool {
+        self.leases
+            .get(seat)
+            .map(|leases| !leases.is_empty())
+            .unwrap_or(false)
+    }
+
+    fn client_ids(&self) -> HashSet<ClientId> {
+        self.leases
+            .values()
+            .flatten()
+            .map(|lease| lease.client_id)
+            .collect()
+    }
+
+    fn is_card_leased(&self, card_node: &Path) -> bool {
+        self.leases
+            .values()
+            .flatten()
+            .any(|lease| lease.infos.iter().any(|info| info.card_node == card_node))
+    }
+
+    fn is_leased(&self, card_node: &Path, display: &DisplayId) -> bool {
+        self.leases
+            .values()
+            .flatten()
+            .any(|lease| lease.contains(card_node, display))
+    }
+
+    /// The pidfds of the lease holders that can be polled for the holder's exit.
+    fn watched_holders(&self) -> Vec<(SeatId, RawFd)> {
+        self.leases
+            .iter()
+            .flat_map(|(seat, leases)| {
+                leases
+                    .iter()
+                    .map(|lease| (seat.clone(), lease.holder.as_fd().as_raw_fd()))
+            })
+            .collect()
+    }
+
+    /// Unregisters the leases of every seat that include displays of the card.
+    fn unregister_on_card(&mut self, card_node: &Path) -> Vec<Lease> {
+        self.unregister_all_if(|lease| lease.infos.iter().any(|info| info.card_node == card_node))
+    }
+
+    fn unregister_with_display(&mut self, card_node: &Path, display: &DisplayId) -> Vec<Lease> {
+        self.unregister_all_if(|lease| lease.contains(card_node, display))
+    }
+
+    fn unregister_of_client(&mut self, client_id: ClientId) -> Vec<Lease> {
+        self.unregister_all_if(|lease| lease.client_id == client_id)
+    }
+
+    fn unregister_held_by(&mut self, seat: &SeatId, peer: &Peer) -> Vec<Lease> {
+        self.unregister_if(seat, |lease| lease.holder.is_same_process(peer))
+    }
+
+    fn unregister_dead(&mut self, seat: &SeatId) -> Vec<Lease> {
+        self.unregister_if(seat, |lease| !lease.holder.is_alive())
+    }
+
+    fn unregister_all_if(&mut self, predicate: impl Fn(&Lease) -> bool) -> Vec<Lease> {
+        let seats: Vec<SeatId> = self.leases.keys().cloned().collect();
+
+        seats
+            .iter()
+            .flat_map(|seat| self.unregister_if(seat, &predicate))
+            .collect()
+    }
+
+    fn unregister_if(&mut self, seat: &SeatId, predicate: impl Fn(&Lease) -> bool) -> Vec<Lease> {
+        let Some(leases) = self.leases.get_mut(seat) else {
+            return vec![];
+        };
+
+        let (unregistered, kept) = std::mem::take(leases).into_iter().partition(predicate);
+        *leases = kept;
+
+        if leases.is_empty() {
+            self.leases.remove(seat);
+        }
+
+        unregistered
+    }
+}
+
+impl Distributor {
+    /// Distributes the displays of the daemon's own seat, or of every seat in the multi-seat mode.
+    pub fn new(
+        plane_policy: PlanePolicy,
+        session_policy: SessionPolicy,
+        multi_seat: bool,
+    ) -> Result<Self, Error> {
+        // The watch exposes the connection fd to the main loop.
+        let mut channel = Channel::get_private(BusType::System)?;
+        channel.set_watch_enabled(true);
+
+        let dbus = Connection::from(channel);
+        let (logind_events_sender, logind_events) = mpsc::channel();
+        dbus.watch_sessions(logind_events_sender.clone())?;
+        dbus.watch_sleep(logind_events_sender.clone())?;
+        dbus.watch_shutdown(logind_events_sender.clone())?;
+
+        let seats = if multi_seat {
+            // Watch before listing, so no seat added meanwhile gets missed.
+            dbus.watch_seats(logind_events_sender)?;
+
+            let seats = dbus.list_seats()?;
+            info!("Running on every Seat: {}", seats.join(", "));
+            seats
+        } else {
+            let seat = dbus.process_seat(std::process::id())?;
+            info!("Running on the Seat \"{seat}\"");
+            vec![seat]
+        };
+
+        // Listen before scanning, so no device plugged in meanwhile gets missed.
+        let monitor = MonitorBuilder::new()?.match_subsystem("drm")?.listen()?;
+
+        let mut distr = Self {
+            dbus,
+            logind_events,
+            seats: seats.iter().cloned().collect(),
+            monitor,
+            plane_policy,
+            session_policy,
+            cards: Default::default(),
+            leases: Default::default(),
+            clients: Default::default(),
+            next_client_id: 0,
+            sleep_inhibitor: None,
+            sleep_deadline: None,
+            shutdown_requested: false,
+            #[cfg(feature = "wayland")]
+            wayland: None,
+        };
+
+        distr.inhibit_sleep();
+
+        for seat in seats {
+            distr.scan_devices(seat)?;
+        }
+
+        Ok(distr)
+    }
+
+    fn scan_devices(&mut self, seat: String) -> Result<(), Error> {
+        info!("Scanning graphics devices of the Seat \"{}\"...", seat);
+
+        for dev in seat_devices(&seat)? {
+            self.process_device(dev)?;
+        }
+        info!("Scanning graphics devices of the Seat \"{}\"...DONE", seat);
+
+        Ok(())
+    }
+
+    fn process_device(&mut self, dev: Device) -> Result<(), Error> {
+        match parse_device(dev) {
+            Some(DrmDevice::Gpu(gpu)) => {
+                self.get_or_add_gpu(gpu)?;
+            }
+            Some(DrmDevice::Display { gpu, seat, display }) => {
+                self.get_or_add_gpu(gpu)?.add_seat_display(seat, display);
+            }
+            None => {}
+        }
+
+        Ok(())
+    }
+
+    fn handle_device_event(&mut self, event: Event) -> Result<(), Error> {
+        let displays_before = self.display_ids_by_seat();
+
+        let result = self.apply_device_event(event);
+        self.notify_display_changes(&displays_before);
+
+        result
+    }
+
+    fn apply_device_event(&mut self, event: Event) -> Result<(), Error> {
+        let dev = event.device();
+        let Some(devtype) = dev.devtype() else {
+            return Ok(());
+        };
+
+        let is_card = dev.sysname().to_string_lossy().contains("card");
+
+        match (event.event_type(), devtype.as_bytes()) {
+            (EventType::Add, _) if self.seats.contains(&device_seat(&dev)) => {
+                self.process_device(dev)?;
+            }
+            (EventType::Change, b"drm_minor")
+                if is_card && is_hotplug(&dev) && self.seats.contains(&device_seat(&dev)) =>
+            {
+                self.rescan_connectors(&dev)?;
+            }
+            (EventType::Remove, b"drm_minor") if is_card => self.remove_gpu(&dev),
+            (EventType::Remove, b"drm_connector") => self.remove_connector(&dev),
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Connectors come and go on hotplug, e.g. the DisplayPort MST ones.
+    fn rescan_connectors(&mut self, gpu: &Device) -> Result<(), Error> {
+        let node = gpu.devnode().expect("GPU must have a node");
+        info!("Hotplug on GPU: {}", gpu.sysname().to_string_lossy());
+
+        let mut connectors_enumerator = Enumerator::new()?;
+        connectors_enumerator.match_is_initialized()?;
+        connectors_enumerator.match_subsystem("drm")?;
+        connectors_enumerator.match_property("DEVTYPE", "drm_connector")?;
+        connectors_enumerator.match_parent(gpu)?;
+
+        if let Some(card) = self.cards.get_mut(node) {
+            card.clear_displays();
+        }
+
+        for dev in connectors_enumerator.scan_devices()? {
+            if self.seats.contains(&device_seat(&dev)) {
+                self.process_device(dev)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// A GPU can disappear at runtime, e.g. when simpledrm gets replaced by the real driver.
+    fn remove_gpu(&mut self, dev: &Device) {
+        let Some(node) = dev.devnode() else {
+            return;
+        };
+
+        if !self.cards.contains_key(node) {
+            return;
+        }
+
+        info!("Removed GPU: {}", dev.sysname().to_string_lossy());
+        self.remove_card(node);
+    }
+
+    fn remove_card(&mut self, node: &Path) {
+        for lease in self.leases.unregister_on_card(node) {
+            info!(
+                "Revoking the lease of the pid {} as its GPU {} is gone",
+                lease.holder.pid(),
+                node.display(),
+            );
+
+            self.revoke_and_notify(&lease, RevokeReason::DeviceRemoved);
+        }
+
+        self.cards.remove(node);
+    }
+
+    fn remove_connector(&mut self, dev: &Device) {
+        // The parent GPU can't be looked up anymore, so the GPU is found by its name.
+        let dev_name = dev.sysname().to_string_lossy().to_string();
+        let Some((gpu_name, display_name)) = dev_name.split_once('-') else {
+            return;
+        };
+
+        let Ok(display_id) = display_name.parse::<DisplayId>() else {
+            return;
+        };
+
+        let Some((card_node, card)) = self
+            .cards
+            .iter_mut()
+            .find(|(_, card)| card.info().sysname == gpu_name)
+        else {
+            return;
+        };
+
+        info!("Removed connector: {gpu_name}/{display_name}");
+        card.remove_display(&display_id);
+
+        let card_node = card_node.clone();
+        for lease in self.leases.unregister_with_display(&card_node, &display_id) {
+            info!(
+                "Revoking the lease of the pid {} as its display {gpu_name}/{display_name} is gone",
+                lease.holder.pid(),
+            );
+
+            self.revoke_and_notify(&lease, RevokeReason::DisplayRemoved);
+        }
+    }
+
+    fn handle_logind_events(&mut self) {
+        let events: Vec<LogindEvent> = self.logind_events.try_iter().collect();
+        for event in events {
+            let displays_before = self.display_ids_by_seat();
+
+            let result = match event {
+                LogindEvent::SeatNew(seat) => self.add_seat(seat),
+                LogindEvent::SeatRemoved(seat) => {
+                    self.remove_seat(&seat);
+                    Ok(())
+                }
+                LogindEvent::SessionRemoved(session) => {
+                    self.revoke_session_leases(&session);
+                    Ok(())
+                }
+                LogindEvent::SessionActiveChanged { session, active } => {
+                    if active {
+                        self.resume_session_leases(&session);
+                    } else {
+                        self.deactivate_session_leases(&session);
+                    }
+                    Ok(())
+                }
+                LogindEvent::PrepareForSleep(true) => {
+                    self.prepare_for_sleep();
+                    Ok(())
+                }
+                LogindEvent::PrepareForSleep(false) => self.resume_from_sleep(),
+                LogindEvent::PrepareForShutdown => {
+                    self.shutdown_requested = true;
+                    Ok(())
+                }
+            };
+
+            if let Err(err) = result {
+                error!("Unable to handle a logind event: {err}");
+            }
+
+            self.notify_display_changes(&displays_before);
+        }
+    }
+
+    fn add_seat(&mut self, seat: SeatId) -> Result<(), Error> {
+        if !self.seats.insert(seat.clone()) {
+            return Ok(());
+        }
+
+        info!("Added Seat \"{seat}\"");
+        self.scan_devices(seat)
+    }
+
+    /// Revokes the seat leases and closes the seat cards left without displays and leases.
+    fn remove_seat(&mut self, seat: &SeatId) {
+        if !self.seats.remove(seat) {
+            return;
+        }
+
+        info!("Removed Seat \"{seat}\"");
+
+        for lease in self.leases.unregister_if(seat, |_| true) {
+            info!(
+                "Revoking the lease of the pid {} as its Seat \"{seat}\" is gone",
+                lease.holder.pid(),
+            );
+
+            self.revoke_and_notify(&lease, RevokeReason::SeatRemoved);
+        }
+
+        let seat_cards: Vec<PathBuf> = self
+            .cards
+            .iter_mut()
+            .filter_map(|(card_node, card)| {
+                card.remove_seat_displays(seat).then(|| card_node.clone())
+            })
+            .collect();
+
+        for card_node in seat_cards {
+            let is_used =
+                self.cards[&card_node].has_displays() || self.leases.is_card_leased(&card_node);
+
+            if !is_used {
+                self.cards.remove(&card_node);
+            }
+        }
+    }
+
+    fn revoke_session_leases(&mut self, session: &SessionPath) {
+        for lease in self
+            .leases
+            .unregister_all_if(|lease| lease.session.path == *session)
+        {
+            info!(
+                "Revoking the lease of the pid {} as its session {session} has ended",
+                lease.holder.pid(),
+            );
+
+            self.revoke_and_notify(&lease, RevokeReason::SessionEnded);
+        }
+    }
+
+    /// Applies the seat's inactive policy to the leases of a session that got switched away from.
+    fn deactivate_session_leases(&mut self, session: &SessionPath) {
+        for mut lease in self
+            .leases
+            .unregister_all_if(|lease| lease.session.path == *session && !lease.suspended)
+        {
+            let seat = lease.session.seat.clone();
+            let accepts_suspend = self
+                .clients
+                .get(&lease.client_id)
+                .is_some_and(ClientConnection::accepts_suspend);
+
+            self.revoke_lease(&lease);
+
+            if accepts_suspend && self.session_policy.on_inactive(&seat) == InactivePolicy::Suspend
+            {
+                info!(
+                    "Suspending the lease of the pid {} as its session {session} is inactive",
+                    lease.holder.pid(),
+                );
+
+                lease.suspend();
+                self.notify_suspended(&lease);
+                self.leases.register(seat, lease);
+            } else {
+                info!(
+                    "Revoking the lease of the pid {} as its session {session} is inactive",
+                    lease.holder.pid(),
+                );
+
+                self.notify_revoked(&lease, RevokeReason::SessionInactive);
+            }
+        }
+    }
+
+    /// Leases the displays of the suspended leases again once their session is active.
+    fn resume_session_leases(&mut self, session: &SessionPath) {
+        for suspended in self
+            .leases
+            .unregister_all_if(|lease| lease.session.path == *session && lease.suspended)
+        {
+            let lease = self.create_lease(
+                &suspended.session,
+                &suspended.holder,
+                suspended.client_id,
+                suspended.selection(),
+                LeaseMode::AllOrNothing,
+            );
+
+            let lease = match lease {
+                Ok(lease) => lease,
+                Err(err) => {
+                    error!(
+                        "Unable to resume the lease of the pid {}: {err}",
+                        suspended.holder.pid(),
+                    );
+
+                    self.notify_revoked(&suspended, RevokeReason::SessionInactive);
+                    continue;
+                }
+            };
+
+            // The lease is released along with the connection.
+            let Some(client) = self.clients.get_mut(&lease.client_id) else {
+                self.revoke_lease(&lease);
+                continue;
+            };
+
+            if let Err(err) = client.send_resumed_lease(&lease) {
+                error!(
+                    "Unable to resume the lease of the pid {}: {err}",
+                    lease.holder.pid(),
+                );
+                self.revoke_lease(&lease);
+                continue;
+            }
+
+            info!(
+                "Resumed the lease of the pid {} as its session {session} is active",
+                lease.holder.pid(),
+            );
+
+            self.leases.register(lease.session.seat.clone(), lease);
+        }
+    }
+
+    fn inhibit_sleep(&mut self) {
+        match self.dbus.inhibit_sleep() {
+            Ok(inhibitor) => self.sleep_inhibitor = Some(inhibitor),
+            Err(err) => {
+                warn!("Unable to delay the sleep, the lessees won't be notified in time: {err}")
+            }
+        }
+    }
+
+    /// Gives the lessees a moment before the sleep, the inhibitor is released once it's over.
+    fn prepare_for_sleep(&mut self) {
+        info!("The system is going to sleep");
+
+        if self.push_to_lessees(ServerMessage::PrepareForSleep) > 0 {
+            self.sleep_deadline = Some(Instant::now() + SLEEP_DELAY);
+        } else {
+            self.sleep_inhibitor = None;
+        }
+    }
+
+    /// Revokes every lease and tells the holders that the distributor is stopping.
+    fn shutdown(&mut self) {
+        for lease in self.leases.unregister_all_if(|_| true) {
+            info!(
+                "Revoking the lease of the pid {} as the distributor is stopping",
+                lease.holder.pid(),
+            );
+
+            self.revoke_and_notify(&lease, RevokeReason::Shutdown);
+
+            // The connection gets closed right after,
+            // so the clients that don't accept events are told why as well.
+            let Some(client) = self
+                .clients
+                .get_mut(&lease.client_id)
+                .filter(|client| !client.accepts_events())
+            else {
+                continue;
+            };
+
+            send_or_log(
+                client,
+                ServerMessage::LeaseRevoked {
+                    reason: RevokeReason::Shutdown,
+                },
+            );
+        }
+
+        for client in self.clients.values_mut() {
+            if let Err(err) = client.flush() {
+                error!(
+                    "Unable to send the pending messages to a client (pid: {}): {err}",
+                    client.pid(),
+                );
+            }
+        }
+
+        #[cfg(feature = "wayland")]
+        self.sync_wayland();
+    }
+
+    /// Lets the system sleep once the lessees have released their leases or run out of time.
+    fn release_sleep_inhibitor(&mut self) {
+        let Some(deadline) = self.sleep_deadline else {
+            return;
+        };
+
+        if Instant::now() < deadline && !self.sleep_lessees().is_empty() {
+            return;
+        }
+
+        self.sleep_deadline = None;
+        self.sleep_inhibitor = None;
+    }
+
+    /// Takes the inhibitor again and catches up with the devices changed during the sleep.
+    fn resume_from_sleep(&mut self) -> Result<(), Error> {
+        info!("The system has resumed");
+
+        self.sleep_deadline = None;
+        if self.sleep_inhibitor.is_none() {
+            self.inhibit_sleep();
+        }
+
+        let result = self.revalidate_devices();
+        self.push_to_lessees(ServerMessage::ResumedFromSleep);
+
+        result
+    }
+
+    /// Rescans the cards and their connectors, revoking the leases whose displays are gone.
+    ///
+    /// Nothing changes until the whole scan succeeds,
+    /// so a failed scan keeps the displays and the leases as they were.
+    fn revalidate_devices(&mut self) -> Result<(), Error> {
+        let mut gpus = HashMap::new();
+        let mut scanned_displays: HashMap<PathBuf, Vec<(SeatId, DisplayId)>> = HashMap::new();
+
+        for seat in self.seats.iter() {
+            for dev in seat_devices(seat)? {
+                let gpu = match parse_device(dev) {
+                    Some(DrmDevice::Gpu(gpu)) => gpu,
+                    Some(DrmDevice::Display { gpu, seat, display }) => {
+                        scanned_displays
+                            .entry(gpu_node(&gpu))
+                            .or_default()
+                            .push((seat, display));
+                        gpu
+                    }
+                    None => continue,
+                };
+
+                gpus.insert(gpu_node(&gpu), gpu);
+            }
+        }
+
+        let mut added_cards = HashMap::new();
+        for (node, gpu) in gpus.iter() {
+            if !self.cards.contains_key(node) {
+                added_cards.insert(node.clone(), open_gpu(gpu, self.plane_policy)?);
+            }
+        }
+
+        let displays_before = self.card_displays();
+
+        let gone_cards: Vec<PathBuf> = self
+            .cards
+            .keys()
+            .filter(|node| !gpus.contains_key(*node))
+            .cloned()
+            .collect();
+
+        for node in gone_cards {
+            info!("The GPU {} is gone during the sleep", node.display());
+            self.remove_card(&node);
+        }
+
+        self.cards.extend(added_cards);
+        for (node, card) in self.cards.iter_mut() {
+            card.clear_displays();
+
+            for (seat, display) in scanned_displays.remove(node).unwrap_or_default() {
+                card.add_seat_display(seat, display);
+            }
+        }
+
+        let gone_displays: Vec<(PathBuf, DisplayId)> = displays_before
+            .difference(&self.card_displays())
+            .cloned()
+            .collect();
+
+        for lease in self.leases.unregister_all_if(|lease| {
+            gone_displays
+                .iter()
+                .any(|(card_node, display)| lease.contains(card_node, display))
+        }) {
+            info!(
+                "Revoking the lease of the pid {} as its displays are gone during the sleep",
+                lease.holder.pid(),
+            );
+
+            self.revoke_and_notify(&lease, RevokeReason::DisplayRemoved);
+        }
+
+        Ok(())
+    }
+
+    /// Every display of every card, whatever its seat.
+    fn card_displays(&self) -> HashSet<(PathBuf, DisplayId)> {
+        self.cards
+            .iter()
+            .flat_map(|(card_node, card)| {
+                card.displays()
+                    .map(move |display| (card_node.clone(), display))
+            })
+            .collect()
+    }
+
+    /// The clients holding a lease that can be told about the sleep.
+    fn sleep_lessees(&self) -> Vec<ClientId> {
+        let lessees = self.leases.client_ids();
+
+        self.clients
+            .iter()
+            .filter(|(client_id, client)| lessees.contains(client_id) && client.accepts_sleep())
+            .map(|(client_id, _)| *client_id)
+            .collect()
+    }
+
+    /// Returns the number of the notified lessees.
+    fn push_to_lessees(&mut self, message: ServerMessage) -> usize {
+        let lessees = self.sleep_lessees();
+
+        for client_id in lessees.iter() {
+            if let Some(client) = self.clients.get_mut(client_id) {
+                send_or_log(client, message.clone());
+            }
+        }
+
+        lessees.len()
+    }
+
+    fn get_or_add_gpu(&mut self, dev: Device) -> Result<&mut Card, Error> {
+        match self.cards.entry(gpu_node(&dev)) {
+            Entry::Vacant(entry) => Ok(entry.insert(open_gpu(&dev, self.plane_policy)?)),
+            Entry::Occupied(entry) => Ok(entry.into_mut()),
+        }
+    }
+
+    pub fn listen_clients(&mut self) -> Result<(), Error> {
+        let socketpath = env::var("DISPLAY_DISTRIBUTOR_SOCKET")?;
+        let socketpath = Path::new(&socketpath);
+
+        let mut signals = SigSet::empty();
+        signals.add(Signal::SIGTERM);
+        signals.add(Signal::SIGINT);
+
+        // The signals are only delivered through the signalfd.
+        // Blocked before binding, so a stop always gets to removing the socket.
+        signals.thread_block().map_err(std::io::Error::from)?;
+        let mut signal_fd =
+            SignalFd::with_flags(&signals, SfdFlags::SFD_NONBLOCK | SfdFlags::SFD_CLOEXEC)
+                .map_err(std::io::Error::from)?;
+
+        if socketpath.try_exists()? {
+            fs::remove_file(&socketpath)?;
+        }
+
+        let listener = UnixListener::bind(socketpath)?;
+        listener.set_nonblocking(true)?;
+
+        let result = self.serve_clients(&listener, &mut signal_fd);
+
+        // No new clients from now on.
+        drop(listener);
+        self.shutdown();
+
+        if let Err(err) = fs::remove_file(socketpath) {
+            error!(
+                "Unable to remove the socket {}: {err}",
+                socketpath.display()
+            );
+        }
+
+        result
+    }
+
+    /// Serves until a stop is requested.
+    fn serve_clients(
+        &mut self,
+        listener: &UnixListener,
+        signal_fd: &mut SignalFd,
+    ) -> Result<(), Error> {
+        loop {
+            dispatch_signals(&self.dbus)?;
+            self.handle_logind_events();
+
+            if self.shutdown_requested {
+                info!("The system is shutting down, stopping");
+                return Ok(());
+            }
+
+            #[cfg(feature = "wayland")]
+            self.sync_wayland();
+
+            let dbus_watch = self.dbus.channel().watch();
+            let mut dbus_flags = PollFlags::POLLIN;
+            if dbus_watch.write {
+                dbus_flags |= PollFlags::POLLOUT;
+            }
+
+            let mut sources = vec![
+                PollSource::Listener,
+                PollSource::Monitor,
+                PollSource::Signals,
+                PollSource::DBus,
+            ];
+            let mut poll_fds = vec![
+                PollFd::new(listener.as_raw_fd(), PollFlags::POLLIN),
+                PollFd::new(self.monitor.as_raw_fd(), PollFlags::POLLIN),
+                PollFd::new(signal_fd.as_raw_fd(), PollFlags::POLLIN),
+                PollFd::new(dbus_watch.fd, dbus_flags),
+            ];
+
+            for (seat, pidfd) in self.leases.watched_holders() {
+                sources.push(PollSource::Holder(seat));
+                poll_fds.push(PollFd::new(pidfd, PollFlags::POLLIN));
+            }
+
+            for (client_id, client) in self.clients.iter() {
+                sources.push(PollSource::Client(*client_id));
+                poll_fds.push(PollFd::new(client.as_raw_fd(), client.poll_flags()));
+            }
+
+            #[cfg(feature = "wayland")]
+            if let Some(bridge) = &self.wayland {
+                sources.push(PollSource::WaylandSocket);
+                poll_fds.push(PollFd::new(bridge.socket_fd(), PollFlags::POLLIN));
+                sources.push(PollSource::WaylandClients);
+                poll_fds.push(PollFd::new(bridge.clients_fd(), PollFlags::POLLIN));
+            }
+
+            match poll(&mut poll_fds, self.poll_timeout()) {
+                Ok(_) => {}
+                Err(Errno::EINTR) => continue,
+                Err(errno) => return Err(std::io::Error::from(errno).into()),
+            }
+
+            for (source, poll_fd) in sources.into_iter().zip(poll_fds) {
+                let Some(revents) = poll_fd.revents().filter(|revents| !revents.is_empty()) else {
+                    continue;
+                };
+
+                match source {
+                    PollSource::Listener => self.accept_clients(listener),
+                    PollSource::Monitor => self.handle_device_events(),
+                    PollSource::Signals => {
+                        if let Ok(Some(signal)) = signal_fd.read_signal() {
+                            let signal = Signal::try_from(signal.ssi_signo as i32)
+                                .map(Signal::as_str)
+                                .unwrap_or("a signal");
+
+                            info!("Received {signal}, stopping");
+                            return Ok(());
+                        }
+                    }
+                    // The signals are handled at the start of the next iteration.
+                    PollSource::DBus => {}
+                    PollSource::Holder(seat) => self.revoke_dead_holder_leases(&seat),
+                    PollSource::Client(client_id) => self.handle_client_events(client_id, revents),
+                    #[cfg(feature = "wayland")]
+                    PollSource::WaylandSocket => self.accept_wayland_clients(),
+                    #[cfg(feature = "wayland")]
+                    PollSource::WaylandClients => self.dispatch_wayland_clients(),
+                }
+            }
+
+            self.drop_stalled_clients();
+            self.release_sleep_inhibitor();
+        }
+    }
+
+    /// Waits until the closest client or sleep deadline, or forever if there is none.
+    fn poll_timeout(&self) -> c_int {
+        let Some(deadline) = self
+            .clients
+            .values()
+            .filter_map(ClientConnection::deadline)
+            .chain(self.sleep_deadline)
+            .min()
+        else {
+            return -1;
+        };
+
+        let timeout = deadline.saturating_duration_since(Instant::now());
+
+        // Rounded up, so the deadline has passed once the poll times out.
+        let timeout_ms = timeout.as_micros().div_ceil(1000);
+        timeout_ms.try_into().unwrap_or(c_int::MAX)
+    }
+
+    fn accept_clients(&mut self, listener: &UnixListener) {
+        loop {
+            let result = match listener.accept() {
+                Ok((stream, _)) => self.add_client(stream),
+                Err(err) if err.kind() == ErrorKind::WouldBlock => return,
+                Err(err) => Err((err.into(), None)),
+            };
+
+            if let Err((err, pid)) = result {
+                error!(
+                    "Unable to handle a client{}: {err}",
+                    pid.map(|pid| format![" (pid: {pid})"]).unwrap_or_default(),
+                );
+            }
+        }
+    }
+
+    fn add_client(&mut self, mut stream: UnixStream) -> Result<(), (Error, Option<pid_t>)> {
+        let peer = Peer::from_stream(&stream).map_err(|e| {
+            reject_client(&mut stream, &e);
+            (e, None)
+        })?;
+        let peer_pid = peer.pid();
+
+        let peer_session = self.peer_session(&peer).map_err(|e| {
+            reject_client(&mut stream, &e);
+            (e, Some(peer_pid))
+        })?;
+        let client_id = self.next_client_id;
+        let client = ClientConnection::new(client_id, stream, peer, peer_session)
+            .map_err(|e| (e, Some(peer_pid)))?;
+
+        self.next_client_id += 1;
+        self.clients.insert(client_id, client);
+
+        Ok(())
+    }
+
+    fn handle_device_events(&mut self) {
+        let events: Vec<Event> = self.monitor.iter().collect();
+        for event in events {
+            if let Err(err) = self.handle_device_event(event) {
+                error!("Unable to handle a device event: {err}");
+            }
+        }
+    }
+
+    fn handle_client_events(&mut self, client_id: ClientId, revents: PollFlags) {
+        // Taken out of the map, so the handlers can borrow both the client and `self`.
+        let Some(mut client) = self.clients.remove(&client_id) else {
+            return;
+        };
+
+        match self.serve_client(&mut client, revents) {
+            Ok(true) if !client.is_finished() => {
+                self.clients.insert(client_id, client);
+                return;
+            }
+            Ok(_) => {}
+            Err(err) => error!("Unable to handle a client (pid: {}): {err}", client.pid()),
+        }
+
+        self.release_client_leases(&client);
+    }
+
+    /// Closing the connection releases the leases granted over it.
+    fn release_client_leases(&mut self, client: &ClientConnection) {
+        for lease in self.leases.unregister_of_client(client.id()) {
+            info!(
+                "The client (pid: {}) has closed the connection, revoking its lease",
+                client.pid(),
+            );
+
+            self.revoke_lease(&lease);
+        }
+    }
+
+    /// Returns `false` once the connection is closed.
+    fn serve_client(
+        &mut self,
+        client: &mut ClientConnection,
+        revents: PollFlags,
+    ) -> Result<bool, Error> {
+        if revents.contains(PollFlags::POLLOUT) {
+            client.flush()?;
+        }
+
+        if !revents.intersects(PollFlags::POLLIN | PollFlags::POLLHUP | PollFlags::POLLERR) {
+            return Ok(true);
+        }
+
+        let is_open = client.read()?;
+        while let Some(message) = client.next_message()? {
+            self.handle_client_message(client, message);
+        }
+        client.flush()?;
+
+        Ok(is_open)
+    }
+
+    fn drop_stalled_clients(&mut self) {
+        let now = Instant::now();
+
+        let stalled_ids: Vec<ClientId> = self
+            .clients
+            .iter()
+            .filter(|(_, client)| {
+                client
+                    .deadline()
+                    .map(|deadline| deadline <= now)
+                    .unwrap_or(false)
+            })
+            .map(|(client_id, _)| *client_id)
+            .collect();
+
+        for client_id in stalled_ids {
+            let Some(client) = self.clients.remove(&client_id) else {
+                continue;
+            };
+
+            warn!("Dropping the stalled client (pid: {})", client.pid());
+            self.release_client_leases(&client);
+        }
+    }
+
+    /// Pushes the message to every client of the seat that accepts events.
+    fn push_event(&mut self, seat: &SeatId, message: ServerMessage) {
+        for client in self.clients.values_mut() {
+            if client.seat() == seat && client.accepts_events() {
+                send_or_log(client, message.clone());
+            }
+        }
+    }
+
+    fn revoke_and_notify(&mut self, lease: &Lease, reason: RevokeReason) {
+        self.revoke_lease(lease);
+        self.notify_revoked(lease, reason);
+    }
+
+    fn notify_revoked(&mut self, lease: &Lease, reason: RevokeReason) {
+        #[cfg(feature = "wayland")]
+        if let Some(bridge) = &mut self.wayland {
+            bridge.finish_lease(lease.client_id);
+        }
+
+        let Some(client) = self.clients.get_mut(&lease.client_id) else {
+            return;
+        };
+
+        if client.accepts_events() {
+            send_or_log(client, ServerMessage::LeaseRevoked { reason });
+        }
+    }
+
+    fn notify_suspended(&mut self, lease: &Lease) {
+        if let Some(client) = self.clients.get_mut(&lease.client_id) {
+            send_or_log(client, ServerMessage::LeaseSuspended);
+        }
+    }
+
+    fn notify_display_changes(
+        &mut self,
+        displays_before: &HashMap<SeatId, HashSet<display_distributor::DisplayId>>,
+    ) {
+        let displays_after = self.display_ids_by_seat();
+        let no_displays = HashSet::new();
+
+        let seats: HashSet<&SeatId> = displays_before
+            .keys()
+            .chain(displays_after.keys())
+            .collect();
+        for seat in seats {
+            let before = displays_before.get(seat).unwrap_or(&no_displays);
+            let after = displays_after.get(seat).unwrap_or(&no_displays);
+
+            let added = after
+                .difference(before)
+                .cloned()
+                .map(ServerMessage::DisplayAdded);
+            let removed = before
+                .difference(after)
+                .cloned()
+                .map(ServerMessage::DisplayRemoved);
+
+            for message in added.chain(removed).collect::<Vec<_>>() {
+                self.push_event(seat, message);
+            }
+        }
+    }
+
+    fn display_ids_by_seat(&self) -> HashMap<SeatId, HashSet<display_distributor::DisplayId>> {
+        self.seats
+            .iter()
+            .map(|seat| (seat.clone(), self.seat_display_ids(seat)))
+            .collect()
+    }
+
+    /// The seat displays as the clients name them, e.g. `card0-HDMI-A-1`.
+    fn seat_display_ids(&self, seat: &SeatId) -> HashSet<display_distributor::DisplayId> {
+        self.cards
+            .values()
+            .flat_map(|card| {
+                card.seat_displays(seat).into_iter().map(|display| {
+                    display_distributor::DisplayId::Connector(format![
+                        "{}-{display}",
+                        card.info().sysname
+                    ])
+                })
+            })
+            .collect()
+    }
+
+    fn peer_session(&self, peer: &Peer) -> Result<Session, Error> {
+        let session = self.dbus.process_session(peer.pid() as u32)?;
+
+        // logind looks the session up by the PID,
+        // which is only meaningful if it still belongs to the peer.
+        if !peer.is_alive() {
+            return Err(Error::PeerGone);
+        }
+
+        Ok(session)
+    }
+
+    /// A connection starts with a handshake and then serves any number of requests.
+    fn handle_client_message(&mut self, client: &mut ClientConnection, message: ClientMessage) {
+        let result = match client.state() {
+            ConnectionState::Handshake => {
+                handshake(client, message).map(|()| ConnectionState::Ready)
+            }
+            ConnectionState::Ready => self
+                .handle_request(client, message)
+                .map(|()| ConnectionState::Ready),
+            ConnectionState::Closing => return,
+        };
+
+        match result {
+            Ok(state) => client.set_state(state),
+            Err(err) => {
+                error!("Unable to handle a client (pid: {}): {err}", client.pid());
+
+                // The error reply, if any, still gets sent.
+                client.set_state(ConnectionState::Closing);
+            }
+        }
+    }
+
+    fn handle_request(
+        &mut self,
+        client: &mut ClientConnection,
+        message: ClientMessage,
+    ) -> Result<(), Error> {
+        use ClientMessage::*;
+
+        match message {
+            RequestDisplays { displays, mode } => {
+                self.handle_request_displays(client, displays, mode)?
+            }
+            ReleaseDisplays => self.handle_release_displays(client)?,
+            Hello { .. } => {
+                client.send_error(&Error::PeerBadMsg)?;
+
+                return Err(Error::PeerBadMsg);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_request_displays(
+        &mut self,
+        client: &mut ClientConnection,
+        displays: Vec<display_distributor::DisplayId>,
+        mode: LeaseMode,
+    ) -> Result<(), Error> {
+        let peer_seat = client.seat().clone();
+        self.revoke_dead_holder_leases(&peer_seat);
+
+        let lease = self
+            .select_displays(&peer_seat, &displays)
+            .and_then(|selection| {
+                self.create_lease(
+                    client.session(),
+                    client.peer(),
+                    client.id(),
+                    selection,
+                    mode,
+                )
+            });
+
+        match lease {
+            Ok(lease) => {
+                if let Err(err) = client.send_lease(&lease) {
+                    self.revoke_lease(&lease);
+                    return Err(err);
+                }
+
+                self.leases.register(peer_seat, lease);
+            }
+            Err(Error::NoDisplays) => client.send_msg(ServerMessage::NoDisplays)?,
+            Err(Error::SeatBusy) => client.send_msg(ServerMessage::SeatBusy)?,
+            Err(Error::UnknownDisplay(display)) => {
+                client.send_msg(ServerMessage::UnknownDisplay(display))?
+            }
+            Err(Error::DisplayUnavailable(display)) => {
+                client.send_msg(ServerMessage::DisplayUnavailable(display))?
+            }
+            Err(Error::AmbiguousDisplay(display)) => {
+                client.send_msg(ServerMessage::AmbiguousDisplay(display))?
+            }
+            Err(Error::DisplayBusy(display)) => {
+                client.send_msg(ServerMessage::DisplayBusy(display))?
+            }
+            Err(Error::LeaseFailed { card, source }) => {
+                client.send_msg(ServerMessage::LeaseFailed {
+                    card: *card,
+                    message: source.to_string(),
+                })?
+            }
+            Err(err) => {
+                error!(
+                    "Unable to lease displays to Seat \"{}\" (pid: {}): {}",
+                    peer_seat,
+                    client.pid(),
+                    err,
+                );
+
+                client.send_error(&err)?
+            }
+        }
+
+        Ok(())
+    }
+
+    fn handle_release_displays(&mut self, client: &mut ClientConnection) -> Result<(), Error> {
+        let peer_seat = client.seat().clone();
+        let released = self.leases.unregister_held_by(&peer_seat, client.peer());
+
+        if !released.is_empty() {
+            for lease in released.iter() {
+                self.revoke_lease(lease);
+            }
+            client.send_msg(ServerMessage::LeaseRevoked {
+                reason: RevokeReason::Released,
+            })?;
+        } else if self.leases.has_leases(&peer_seat) {
+            client.send_msg(ServerMessage::NoPermission)?;
+        } else {
+            client.send_msg(ServerMessage::LeaseNotFound)?;
+        }
+
+        Ok(())
+    }
+
+    /// Resolves the displays a client asks for into the seat's displays.
+    ///
+    /// No displays means every display of the seat.
+    fn select_displays(
+        &self,
+        seat: &SeatId,
+        requested: &[display_distributor::DisplayId],
+    ) -> Result<DisplaySelection, Error> {
+        let mut selection = DisplaySelection::new();
+
+        if requested.is_empty() {
+            for (card_node, card) in self.cards.iter() {
+                let displays = card.seat_displays(seat);
+                if displays.is_empty() {
+                    continue;
+                }
+
+                if displays
+                    .iter()
+                    .any(|display| self.leases.is_leased(card_node, display))
+                {
+                    return Err(Error::SeatBusy);
+                }
+
+                selection.insert(card_node.clone(), displays);
+            }
+
+            if selection.is_empty() {
+                return Err(Error::NoDisplays);
+            }
+
+            return Ok(selection);
+        }
+
+        for requested_display in requested {
+            let (card_node, display) = self.find_display(seat, requested_display)?;
+
+            if self.leases.is_leased(&card_node, &display) {
+                return Err(Error::DisplayBusy(requested_display.clone()));
+            }
+
+            selection.entry(card_node).or_default().insert(display);
+        }
+
+        Ok(selection)
+    }
+
+    fn find_display(
+        &self,
+        seat: &SeatId,
+        requested: &display_distributor::DisplayId,
+    ) -> Result<(PathBuf, DisplayId), Error> {
+        use display_distributor::DisplayId::*;
+
+        let mut found = vec![];
+        for (card_node, card) in self.cards.iter() {
+            let displays = match requested {
+                Connector(name) => card.find_connector(name)?.into_iter().collect(),
+                Edid(edid_id) => card.find_edid(edid_id)?,
+            };
+
+            found.extend(
+                displays
+                    .into_iter()
+                    .map(|display| (card_node, card, display)),
+            );
+        }
+
+        let seat_displays: Vec<_> = found
+            .iter()
+            .filter(|(_, card, display)| card.display_seat(display) == Some(seat))
+            .collect();
+
+        match seat_displays.as_slice() {
+            [(card_node, _, display)] => Ok(((*card_node).clone(), *display)),
+            [] if found.is_empty() => Err(Error::UnknownDisplay(requested.clone())),
+            [] => Err(Error::DisplayUnavailable(requested.clone())),
+            _ => Err(Error::AmbiguousDisplay(requested.clone())),
+        }
+    }
+
+    fn create_lease(
+        &mut self,
+        session: &Session,
+        holder: &Peer,
+        client_id: ClientId,
+        selection: DisplaySelection,
+        mode: LeaseMode,
+    ) -> Result<Lease, Error> {
+        let peer_seat = &session.seat;
+        let session_state = self.dbus.session_state(&session.path)?;
+        self.session_policy.check(&session_state)?;
+
+        let selection = skip_gone_cards(selection, mode, |card_node| {
+            self.cards.contains_key(card_node)
+        })?;
+
+        let mut lease = Lease::new(holder.try_clone()?, session.clone(), client_id);
+
+        for (card_node, displays) in selection {
+            let card = self
+                .cards
+                .get_mut(&card_node)
+                .expect("Gone cards are skipped above");
+
+            match card.lease_displays(&displays) {
+                Ok(displays_lease) => {
+                    lease.add_displays(card_node, card.info().clone(), displays, displays_lease)
+                }
+                Err(Error::NoDisplays) => {}
+                Err(err) if mode == LeaseMode::AllOrNothing => {
+                    error!(
+                        "Unable to lease {} displays to Seat \"{}\", rolling back the lease: {}",
+                        card_node.display(),
+                        peer_seat,
+                        err,
+                    );
+
+                    let card = Box::new(card.info().clone());
+                    self.revoke_lease(&lease);
+
+                    return Err(Error::LeaseFailed {
+                        card,
+                        source: Box::new(err),
+                    });
+                }
+                Err(err) => error!(
+                    "Unable to lease {} displays to Seat \"{}\": {}",
+                    card_node.display(),
+                    peer_seat,
+                    err,
+                ),
+            }
+        }
+
+        if lease.lease_fds.is_empty() {
+            Err(Error::NoDisplays)
+        } else {
+            Ok(lease)
+        }
+    }
+
+    fn revoke_dead_holder_leases(&mut self, seat: &SeatId) {
+        for lease in self.leases.unregister_dead(seat) {
+            info!(
+                "The lease holder of the Seat \"{seat}\" (pid: {}) is gone, revoking its lease",
+                lease.holder.pid(),
+            );
+
+            self.revoke_and_notify(&lease, RevokeReason::HolderExited);
+        }
+    }
+
+    fn revoke_lease(&mut self, lease: &Lease) {
+        // A suspended lease has no DRM leases left.
+        if lease.suspended {
+            return;
+        }
+
+        for lease_info in lease.infos.iter() {
+            let LeaseInfo {
+                card_node,
+                lessee_id,
+                ..
+            } = lease_info;
+
+            let Some(card) = self.cards.get_mut(card_node) else {
+                warn!(
+                    "A lease points to the device {} that is not found",
+                    card_node.display()
+                );
+                continue;
+            };
+
+            if let Err(err) = card.revoke_displays(*lessee_id) {
+                error!(
+                    "Unable to revoke a lease on the device {}: {}",
+                    card_node.display(),
+                    err
+                );
+            }
+        }
+    }
+}
+
+/// Hands every queued DBus signal over to its handler.
+///
+/// The blocking DBus calls, e.g. the session lookups, queue the signals received meanwhile.
+/// A queued signal doesn't make the DBus fd readable, so the queue is drained before every poll.
+fn dispatch_signals(bus: &impl Dispatch) -> Result<(), Error> {
+    while bus.dispatch()? {}
+
+    Ok(())
+}
+
+/// Drops the cards gone since the displays were selected,
+/// unless the lease is all or nothing, which fails instead.
+fn skip_gone_cards(
+    mut selection: DisplaySelection,
+    mode: LeaseMode,
+    is_present: impl Fn(&Path) -> bool,
+) -> Result<DisplaySelection, Error> {
+    if mode == LeaseMode::AllOrNothing {
+        let gone_display = selection
+            .iter()
+            .filter(|(card_node, _)| !is_present(card_node))
+            .find_map(|(card_node, displays)| Some((card_node, displays.iter().next()?)));
+
+        if let Some((card_node, display)) = gone_display {
+            let sysname = card_node.file_name().unwrap_or_default().to_string_lossy();
+
+            return Err(Error::DisplayUnavailable(
+                display_distributor::DisplayId::Connector(format!["{sysname}-{display}"]),
+            ));
+        }
+    }
+
+    selection.retain(|card_node, _| {
+        let is_present = is_present(card_node);
+        if !is_present {
+            warn!(
+                "Skipping the displays of the gone GPU {}",
+                card_node.display()
+            );
+        }
+
+        is_present
+    });
+
+    Ok(selection)
+}
+
+/// Tells a client refused right on connect why, instead of just closing the connection.
+///
+/// The frame is small enough for the empty socket buffer, so the write doesn't block.
+fn reject_client(stream: &mut UnixStream, err: &Error) {
+    let message = ServerMessage::Error {
+        code: err.code(),
+        message: err.to_string(),
+    };
+
+    if let Err(write_err) = framing::write_frame(stream, &message) {
+        warn!("Unable to tell a refused client why: {write_err}");
+    }
+}
+
+/// Sends a message no reply depends on, a failure is only logged.
+fn send_or_log(client: &mut ClientConnection, message: ServerMessage) {
+    if let Err(err) = client.send_msg(message) {
+        error!("Unable to notify a client (pid: {}): {err}", client.pid());
+    }
+}
+
+/// Checks the protocol version and agrees on the features with a freshly connected client.
+fn handshake(client: &mut ClientConnection, message: ClientMessage) -> Result<(), Error> {
+    let ClientMessage::Hello {
+        protocol_version,
+        client_name,
+        capabilities,
+    } = message
+    else {
+        client.send_error(&Error::NoHandshake)?;
+
+        return Err(Error::NoHandshake);
+    };
+
+    if let Some(reply) = version_refusal(protocol_version) {
+        client.send_msg(reply)?;
+
+        return Err(Error::UnsupportedProtocolVersion(protocol_version));
+    }
+
+    let capabilities = capabilities.intersection(Capabilities::SUPPORTED);
+    client.set_capabilities(capabilities);
+    client.send_msg(ServerMessage::Hello {
+        protocol_version: PROTOCOL_VERSION,
+        capabilities,
+    })?;
+
+    info!(
+        "Client \"{client_name}\" (pid: {}) speaks the protocol v{PROTOCOL_VERSION} with capabilities {:#x}",
+        client.pid(),
+        capabilities.bits(),
+    );
+
+    Ok(())
+}
+
+/// A scanned DRM device the distributor keeps track of.
+enum DrmDevice {
+    Gpu(Device),
+    Display {
+        gpu: Device,
+        seat: SeatId,
+        display: DisplayId,
+    },
+}
+
+/// The GPUs and connectors of the seat.
+fn seat_devices(seat: &SeatId) -> Result<Vec<Device>, Error> {
+    let mut cards_enumerator = Enumerator::new()?;
+    cards_enumerator.match_is_initialized()?;
+    cards_enumerator.match_subsystem("drm")?;
+    cards_enumerator.match_property("DEVTYPE", "drm_minor")?;
+    cards_enumerator.match_property("DEVTYPE", "drm_connector")?;
+
+    Ok(cards_enumerator
+        .scan_devices()?
+        .filter(|dev| device_seat(dev) == *seat)
+        .collect())
+}
+
+fn parse_device(dev: Device) -> Option<DrmDevice> {
+    match dev
+        .devtype()
+        .expect("Invalid device got matched")
+        .as_bytes()
+    {
+        b"drm_minor" if dev.sysname().to_string_lossy().contains("card") => {
+            Some(DrmDevice::Gpu(dev))
+        }
+        b"drm_connector" => {
+            let gpu = dev.parent().expect("Connectors always have a parent GPU");
+            let gpu_name = gpu.sysname().to_string_lossy().to_string();
+
+            let display_seat = device_seat(&dev);
+            let dev_name = dev.sysname().to_string_lossy().to_string();
+
+            let display_name = dev_name
+                .strip_prefix(&format!["{gpu_name}-"])
+                .expect("Connetcors always prefixed with the GPU name");
+
+            info!(
+                "Detected Seat \"{}\" connector: {}/{}",
+                display_seat, gpu_name, display_name,
+            );
+
+            match display_name.parse() {
+                Ok(display) => Some(DrmDevice::Display {
+                    gpu,
+                    seat: display_seat,
+                    display,
+                }),
+                Err(err) => {
+                    warn!("Skipping the connector {gpu_name}/{display_name}: {err}");
+                    None
+                }
+            }
+        }
+        _ => None,
+    }
+}
+
+fn gpu_node(gpu: &Device) -> PathBuf {
+    gpu.devnode().expect("GPU must have a node").to_path_buf()
+}
+
+fn open_gpu(gpu: &Device, plane_policy: PlanePolicy) -> Result<Card, Error> {
+    info!("Detected GPU: {}", gpu.sysname().to_string_lossy());
+
+    Card::new(gpu, plane_policy)
+}
+
+/// Resolves the seat of a device the same way logind does.
+///
+/// A device belongs to the seat from its `ID_SEAT` property.
+/// The udev seat rules import `ID_SEAT` from the parent devices,
+/// so e.g. a connector belongs to the seat of its GPU and a GPU to the seat of its PCI slot.
+/// The rules also turn `ID_AUTOSEAT` into `ID_SEAT`, so it isn't looked at here.
+fn device_seat(dev: &Device) -> SeatId {
+    let ancestors = iter::successors(Some(dev.clone()), Device::parent);
+
+    resolve_seat(ancestors.map(|dev| {
+        dev.property_value("ID_SEAT")
+            .map(|seat| seat.to_string_lossy().to_string())
+    }))
+}
+
+/// Picks the first non-empty `ID_SEAT` of a device and its parents, from the device up.
+/// A device without `ID_SEAT` anywhere up the tree belongs to the default seat.
+fn resolve_seat(seats: impl IntoIterator<Item = Option<String>>) -> SeatId {
+    seats
+        .into_iter()
+        .flatten()
+        .find(|seat| !seat.is_empty())
+        .unwrap_or_else(|| DEFAULT_SEAT.to_string())
+}
+
+/// The kernel sends `HOTPLUG=1` along with a card change event when its outputs change.
+fn is_hotplug(dev: &Device) -> bool {
+    dev.property_value("HOTPLUG")
+        .map(|hotplug| hotplug == "1")
+        .unwrap_or(false)
+}
+
+trait ServerMessageSend {
+    fn send_msg(&mut self, message: ServerMessage) -> Result<(), Error>;
+
+    fn send_msg_fds(&mut self, message: ServerMessage, fds: &[BorrowedFd]) -> Result<(), Error>;
+
+    fn send_error(&mut self, err: &Error) -> Result<(), Error> {
+        self.send_msg(ServerMessage::Error {
+            code: err.code(),
+            message: err.to_string(),
+        })
+    }
+}
+
+impl ServerMessageSend for ClientConnection {
+    fn send_msg(&mut self, message: ServerMessage) -> Result<(), Error> {
+        self.queue_frame(framing::encode(&message)?, vec![]);
+
+        Ok(())
+    }
+
+    fn send_msg_fds(&mut self, message: ServerMessage, fds: &[BorrowedFd]) -> Result<(), Error> {
+        let frame = framing::encode(&message)?;
+
+        // The queued fds must outlive whatever happens to the originals until they are sent.
+        let fds = fds
+            .iter()
+            .map(|fd| fd.try_clone_to_owned())
+            .collect::<Result<Vec<_>, _>>()?;
+
+        self.queue_frame(frame, fds);
+
+        Ok(())
+    }
+}
+
+trait LeaseSend {
+    fn send_lease(&mut self, lease: &Lease) -> Result<(), Error>;
+
+    fn send_resumed_lease(&mut self, lease: &Lease) -> Result<(), Error>;
+}
+
+impl LeaseSend for ClientConnection {
+    fn send_lease(&mut self, lease: &Lease) -> Result<(), Error> {
+        let message = ServerMessage::LeaseGranted {
+            leases: lease.grants(),
+        };
+
+        self.send_msg_fds(message, &lease.fds())
+    }
+
+    fn send_resumed_lease(&mut self, lease: &Lease) -> Result<(), Error> {
+        let message = ServerMessage::LeaseResumed {
+            leases: lease.grants(),
+        };
+
+        self.send_msg_fds(message, &lease.fds())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        cell::RefCell,
+        collections::VecDeque,
+        process::{self, Command},
+        sync::mpsc::Sender,
+    };
+
+    use drm::control::connector::Interface;
+
+    use super::*;
+    use crate::dbus::SessionState;
+
+    /// A bus that receives a signal during every blocking call,
+    /// queuing it the way a connection does while waiting for a reply.
+    struct FakeBus {
+        queued: RefCell<VecDeque<LogindEvent>>,
+        events: Sender<LogindEvent>,
+    }
+
+    impl Sessions for FakeBus {
+        fn session_state(&self, session: &SessionPath) -> Result<SessionState, Error> {
+            // The session gets switched away from while its state is awaited.
+            self.queued
+                .borrow_mut()
+                .push_back(LogindEvent::SessionActiveChanged {
+                    session: session.clone(),
+                    active: false,
+                });
+
+            Ok(SessionState {
+                active: true,
+                class: "user".to_string(),
+                remote: false,
+            })
+        }
+
+        fn watch_sessions(&self, _: Sender<LogindEvent>) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    impl Dispatch for FakeBus {
+        fn dispatch(&self) -> Result<bool, Error> {
+            let Some(event) = self.queued.borrow_mut().pop_front() else {
+                return Ok(false);
+            };
+
+            self.events.send(event).unwrap();
+            Ok(true)
+        }
+    }
+
+    #[test]
+    fn signals_queued_during_blocking_calls_are_dispatched() {
+        let (events_sender, events) = mpsc::channel();
+        let bus = FakeBus {
+            queued: Default::default(),
+            events: events_sender,
+        };
+        let session = SessionPath::from("/org/freedesktop/login1/session/_31");
+
+        bus.session_state(&session).unwrap();
+        bus.session_state(&session).unwrap();
+        assert!(events.try_recv().is_err());
+
+        dispatch_signals(&bus).unwrap();
+
+        let dispatched: Vec<LogindEvent> = events.try_iter().collect();
+        assert_eq!(dispatched.len(), 2);
+        for event in dispatched {
+            assert!(
+                matches!(
+                    &event,
+                    LogindEvent::SessionActiveChanged { session: changed, active: false }
+                        if *changed == session
+                ),
+                "{event:?}",
+            );
+        }
+    }
+
+    fn hdmi(id: u32) -> DisplayId {
+        DisplayId(Interface::HDMIA, id)
+    }
+
+    fn selection(cards: &[(&str, &[DisplayId])]) -> DisplaySelection {
+        cards
+            .iter()
+            .map(|(card_node, displays)| (card_node.into(), displays.iter().copied().collect()))
+            .collect()
+    }
+
+    #[test]
+    fn gone_cards_fail_all_or_nothing_leases() {
+        let requested = selection(&[
+            ("/dev/dri/card0", &[hdmi(1)]),
+            ("/dev/dri/card1", &[hdmi(2)]),
+        ]);
+        let is_present = |card_node: &Path| card_node == Path::new("/dev/dri/card0");
+
+        let result = skip_gone_cards(requested, LeaseMode::AllOrNothing, is_present);
+        assert!(
+            matches!(
+                &result,
+                Err(Error::DisplayUnavailable(display_distributor::DisplayId::Connector(name)))
+                    if name == "card1-HDMI-A-2"
+            ),
+            "{result:?}",
+        );
+    }
+
+    #[test]
+    fn gone_cards_are_skipped_by_best_effort_leases() {
+        let requested = selection(&[
+            ("/dev/dri/card0", &[hdmi(1)]),
+            ("/dev/dri/card1", &[hdmi(2)]),
+        ]);
+        let is_present = |card_node: &Path| card_node == Path::new("/dev/dri/card0");
+
+        let selected = skip_gone_cards(requested, LeaseMode::BestEffort, is_present).unwrap();
+        assert_eq!(selected, selection(&[("/dev/dri/card0", &[hdmi(1)])]));
+    }
+
+    #[test]
+    fn present_cards_are_kept() {
+        let requested = selection(&[
+            ("/dev/dri/card0", &[hdmi(1)]),
+            ("/dev/dri/card1", &[hdmi(2)]),
+        ]);
+
+        for mode in [LeaseMode::AllOrNothing, LeaseMode::BestEffort] {
+            let selected = skip_gone_cards(requested.clone(), mode, |_| true).unwrap();
+            assert_eq!(selected, requested, "{mode:?}");
+        }
+    }
+
+    #[test]
+    fn resolves_device_seats() {
+        let cases: [(&[Option<&str>], &str); 8] = [
+            (&[Some("seat1")], "seat1"),
+            (&[Some("seat1"), Some("seat2")], "seat1"),
+            (&[None, Some("seat1")], "seat1"),
+            (&[None, None, Some("seat-usb")], "seat-usb"),
+            (&[Some(""), Some("seat1")], "seat1"),
+            (&[None, Some(""), None], DEFAULT_SEAT),
+            (&[None, None], DEFAULT_SEAT),
+            (&[], DEFAULT_SEAT),
+        ];
+
+        for (seats, expected) in cases {
+            let resolved = resolve_seat(seats.iter().map(|seat| seat.map(str::to_string)));
+            assert_eq!(resolved, expected, "{seats:?}");
+        }
+    }
+
+    fn own_process() -> Peer {
+        Peer::from_pid(process::id() as libc::pid_t).unwrap()
+    }
+
+    fn lease(
+        holder: Peer,
+        seat: &str,
+        client_id: ClientId,
+        cards: &[(&str, &[DisplayId])],
+    ) -> Lease {
+        let session = Session {
+            path: SessionPath::from("/org/freedesktop/login1/session/_31"),
+            seat: seat.to_string(),
+        };
+
+        let mut lease = Lease::new(holder, session, client_id);
+        lease.infos = selection(cards)
+            .into_iter()
+            .map(|(card_node, displays)| LeaseInfo {
+                grant: LeaseGrant {
+                    card: CardInfo {
+                        devnode: card_node.clone(),
+                        sysname: card_node.file_name().unwrap().to_string_lossy().to_string(),
+                        driver: None,
+                        pci: None,
+                    },
+                    lessee_id: 1,
+                    connectors: vec![],
+                    crtcs: vec![],
+                    planes: vec![],
+                },
+                card_node,
+                lessee_id: LesseeId::new(1).unwrap(),
+                displays,
+            })
+            .collect();
+
+        lease
+    }
+
+    fn registry(leases: impl IntoIterator<Item = Lease>) -> LeaseRegistry {
+        let mut registry = LeaseRegistry::default();
+        for lease in leases {
+            registry.register(lease.session.seat.clone(), lease);
+        }
+
+        registry
+    }
+
+    fn client_ids(leases: &[Lease]) -> Vec<ClientId> {
+        let mut client_ids: Vec<ClientId> = leases.iter().map(|lease| lease.client_id).collect();
+        client_ids.sort();
+        client_ids
+    }
+
+    #[test]
+    fn leases_are_kept_per_seat() {
+        let mut leases = registry([
+            lease(own_process(), "seat0", 1, &[("/dev/dri/card0", &[hdmi(1)])]),
+            lease(own_process(), "seat1", 2, &[("/dev/dri/card1", &[hdmi(1)])]),
+        ]);
+
+        assert!(leases.has_leases(&"seat0".to_string()));
+        assert!(leases.has_leases(&"seat1".to_string()));
+        assert!(!leases.has_leases(&"seat2".to_string()));
+        assert_eq!(leases.client_ids(), HashSet::from([1, 2]));
+
+        let removed = leases.unregister_if(&"seat0".to_string(), |_| true);
+        assert_eq!(client_ids(&removed), [1]);
+        assert!(!leases.has_leases(&"seat0".to_string()));
+        assert!(!leases.leases.contains_key("seat0"));
+        assert!(leases.has_leases(&"seat1".to_string()));
+        assert!(leases.is_card_leased(Path::new("/dev/dri/card1")));
+    }
+
+    #[test]
+    fn double_registration_keeps_both_leases() {
+        let mut leases = registry([
+            lease(own_process(), "seat0", 1, &[("/dev/dri/card0", &[hdmi(1)])]),
+            lease(own_process(), "seat0", 1, &[("/dev/dri/card0", &[hdmi(2)])]),
+        ]);
+
+        assert_eq!(leases.leases["seat0"].len(), 2);
+        assert!(leases.is_leased(Path::new("/dev/dri/card0"), &hdmi(1)));
+        assert!(leases.is_leased(Path::new("/dev/dri/card0"), &hdmi(2)));
+
+        let removed = leases.unregister_of_client(1);
+        assert_eq!(client_ids(&removed), [1, 1]);
+        assert!(!leases.has_leases(&"seat0".to_string()));
+    }
+
+    #[test]
+    fn unregistering_from_an_unknown_seat_changes_nothing() {
+        let mut leases = registry([lease(
+            own_process(),
+            "seat0",
+            1,
+            &[("/dev/dri/card0", &[hdmi(1)])],
+        )]);
+
+        assert!(leases
+            .unregister_if(&"seat1".to_string(), |_| true)
+            .is_empty());
+        assert!(leases.unregister_dead(&"seat1".to_string()).is_empty());
+        assert!(!leases.leases.contains_key("seat1"));
+        assert!(leases.has_leases(&"seat0".to_string()));
+    }
+
+    #[test]
+    fn unregisters_leases_by_display_and_card_on_every_seat() {
+        let mut leases = registry([
+            lease(own_process(), "seat0", 1, &[("/dev/dri/card0", &[hdmi(1)])]),
+            lease(
+                own_process(),
+                "seat1",
+                2,
+                &[
+                    ("/dev/dri/card0", &[hdmi(2)]),
+                    ("/dev/dri/card1", &[hdmi(1)]),
+                ],
+            ),
+            lease(own_process(), "seat1", 3, &[("/dev/dri/card2", &[hdmi(1)])]),
+        ]);
+
+        // HDMI-A-2 is leased on another card only.
+        let removed = leases.unregister_with_display(Path::new("/dev/dri/card1"), &hdmi(2));
+        assert!(removed.is_empty());
+
+        let removed = leases.unregister_with_display(Path::new("/dev/dri/card0"), &hdmi(1));
+        assert_eq!(client_ids(&removed), [1]);
+        assert!(!leases.has_leases(&"seat0".to_string()));
+
+        // The whole lease goes, along with its displays on the other cards.
+        let removed = leases.unregister_on_card(Path::new("/dev/dri/card0"));
+        assert_eq!(client_ids(&removed), [2]);
+        assert!(!leases.is_card_leased(Path::new("/dev/dri/card0")));
+        assert!(!leases.is_card_leased(Path::new("/dev/dri/card1")));
+        assert!(leases.is_leased(Path::new("/dev/dri/card2"), &hdmi(1)));
+        assert_eq!(leases.client_ids(), HashSet::from([3]));
+    }
+
+    #[test]
+    fn unregisters_leases_of_holders_on_their_seat_only() {
+        let mut child = Command::new("true").spawn().unwrap();
+        let dead_holder = Peer::from_pid(child.id() as libc::pid_t).unwrap();
+        child.wait().unwrap();
+
+        let mut leases = registry([
+            lease(dead_holder, "seat0", 1, &[("/dev/dri/card0", &[hdmi(1)])]),
+            lease(own_process(), "seat0", 2, &[("/dev/dri/card0", &[hdmi(2)])]),
+            lease(own_process(), "seat1", 3, &[("/dev/dri/card1", &[hdmi(1)])]),
+        ]);
+
+        let removed = leases.unregister_dead(&"seat0".to_string());
+        assert_eq!(client_ids(&removed), [1]);
+
+        let removed = leases.unregister_held_by(&"seat0".to_string(), &own_process());
+        assert_eq!(client_ids(&removed), [2]);
+        assert!(!leases.has_leases(&"seat0".to_string()));
+        assert_eq!(leases.client_ids(), HashSet::from([3]));
+    }
+}
@@ -0,0 +1,189 @@
+use std::collections::HashMap;
+
+use clap::ValueEnum;
+
+use crate::{dbus::SessionState, Error};
+
+use super::SeatId;
+
+/// The session classes that may hold a lease.
+const LEASE_CLASSES: [&str; 2] = ["user", "greeter"];
+
+/// What happens to the leases of a session once another session on the seat becomes active.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum InactivePolicy {
+    /// Revoke the leases, the displays are free for the active session.
+    #[default]
+    Revoke,
+
+    /// Revoke the DRM leases but keep the displays for the holder,
+    /// they are leased again once its session is active.
+    ///
+    /// The leases of the clients that can't follow a suspension are revoked instead.
+    Suspend,
+}
+
+/// Decides which sessions may hold leases and what happens to them once inactive.
+pub struct SessionPolicy {
+    inactive: InactivePolicy,
+    seat_inactive: HashMap<SeatId, InactivePolicy>,
+}
+
+impl SessionPolicy {
+    pub fn new(
+        inactive: InactivePolicy,
+        seat_inactive: impl IntoIterator<Item = (SeatId, InactivePolicy)>,
+    ) -> Self {
+        Self {
+            inactive,
+            seat_inactive: seat_inactive.into_iter().collect(),
+        }
+    }
+
+    pub fn on_inactive(&self, seat: &SeatId) -> InactivePolicy {
+        self.seat_inactive
+            .get(seat)
+            .copied()
+            .unwrap_or(self.inactive)
+    }
+
+    /// Only the active local sessions of the lease classes may hold a lease.
+    pub fn check(&self, state: &SessionState) -> Result<(), Error> {
+        if state.remote {
+            return Err(Error::RemoteSession);
+        }
+
+        if !LEASE_CLASSES.contains(&state.class.as_str()) {
+            return Err(Error::SessionClass(state.class.clone()));
+        }
+
+        if !state.active {
+            return Err(Error::InactiveSession);
+        }
+
+        Ok(())
+    }
+}
+
+/// Parses a seat policy given as `<seat>=<policy>`, e.g. `seat1=suspend`.
+pub fn parse_seat_policy(value: &str) -> Result<(SeatId, InactivePolicy), String> {
+    let (seat, policy) = value
+        .split_once('=')
+        .ok_or_else(|| format!("expected `<seat>=<policy>`, got `{value}`"))?;
+
+    Ok((seat.to_string(), InactivePolicy::from_str(policy, true)?))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn state(active: bool, class: &str, remote: bool) -> SessionState {
+        SessionState {
+            active,
+            class: class.to_string(),
+            remote,
+        }
+    }
+
+    /// Names the refusal to keep the cases short.
+    fn refusal(result: Result<(), Error>) -> Result<(), String> {
+        result.map_err(|err| match err {
+            Error::RemoteSession => "remote".to_string(),
+            Error::SessionClass(class) => format!("class {class}"),
+            Error::InactiveSession => "inactive".to_string(),
+            err => panic!("Unexpected error: {err}"),
+        })
+    }
+
+    #[test]
+    fn checks_sessions() {
+        let cases = [
+            (state(true, "user", false), Ok(())),
+            (state(true, "greeter", false), Ok(())),
+            (state(false, "user", false), Err("inactive")),
+            (state(false, "greeter", false), Err("inactive")),
+            (state(true, "user", true), Err("remote")),
+            (state(true, "lock-screen", false), Err("class lock-screen")),
+            (state(true, "background", false), Err("class background")),
+            (state(true, "", false), Err("class ")),
+            // A remote session is refused before its class is looked at,
+            (state(false, "background", true), Err("remote")),
+            // and a session of another class before its activity.
+            (state(false, "background", false), Err("class background")),
+        ];
+
+        let policy = SessionPolicy::new(InactivePolicy::Revoke, []);
+        for (state, expected) in cases {
+            assert_eq!(
+                refusal(policy.check(&state)),
+                expected.map_err(str::to_string),
+                "{state:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn seat_policies_override_the_default() {
+        let policy = SessionPolicy::new(
+            InactivePolicy::Revoke,
+            [("seat1".to_string(), InactivePolicy::Suspend)],
+        );
+        assert_eq!(
+            policy.on_inactive(&"seat0".to_string()),
+            InactivePolicy::Revoke
+        );
+        assert_eq!(
+            policy.on_inactive(&"seat1".to_string()),
+            InactivePolicy::Suspend
+        );
+
+        let policy = SessionPolicy::new(
+            InactivePolicy::Suspend,
+            [("seat1".to_string(), InactivePolicy::Revoke)],
+        );
+        assert_eq!(
+            policy.on_inactive(&"seat0".to_string()),
+            InactivePolicy::Suspend
+        );
+        assert_eq!(
+            policy.on_inactive(&"seat1".to_string()),
+            InactivePolicy::Revoke
+        );
+    }
+
+    #[test]
+    fn parses_seat_policies() {
+        let cases = [
+            ("seat0=revoke", ("seat0", InactivePolicy::Revoke)),
+            ("seat1=suspend", ("seat1", InactivePolicy::Suspend)),
+            ("seat1=Suspend", ("seat1", InactivePolicy::Suspend)),
+            ("seat-usb=REVOKE", ("seat-usb", InactivePolicy::Revoke)),
+        ];
+
+        for (value, (seat, policy)) in cases {
+            assert_eq!(
+                parse_seat_policy(value),
+                Ok((seat.to_string(), policy)),
+                "{value}"
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_seat_policies() {
+        let cases = [
+            "",
+            "seat1",
+            "suspend",
+            "seat1=",
+            "seat1=pause",
+            "seat1:suspend",
+            "seat1=suspend=revoke",
+        ];
+
+        for value in cases {
+            assert!(parse_seat_policy(value).is_err(), "{value}");
+        }
+    }
+}
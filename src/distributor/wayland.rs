@@ -0,0 +1,593 @@
+//! Serves the `wp_drm_lease_device_v1` protocol, so Wayland clients get leases without a compositor.
+//!
+//! Every card is advertised as a lease device global.
+//! A bound device offers the displays of the client's seat that aren't leased,
+//! and a submitted lease request goes through the same seat checks as a native request.
+
+use std::{
+    collections::HashMap,
+    fs::File,
+    mem,
+    os::fd::{AsFd, AsRawFd, RawFd},
+    path::PathBuf,
+    sync::{Arc, Mutex},
+};
+
+use display_distributor::LeaseMode;
+use log::{error, info, warn};
+use wayland_protocols::wp::drm_lease::v1::server::{
+    wp_drm_lease_connector_v1::{self, WpDrmLeaseConnectorV1},
+    wp_drm_lease_device_v1::{self, WpDrmLeaseDeviceV1},
+    wp_drm_lease_request_v1::{self, WpDrmLeaseRequestV1},
+    wp_drm_lease_v1::{self, WpDrmLeaseV1},
+};
+use wayland_server::{
+    backend::{ClientData, GlobalId},
+    Client, DataInit, Dispatch, Display, DisplayHandle, GlobalDispatch, ListeningSocket, New,
+    Resource,
+};
+
+use crate::{
+    connection::ClientId,
+    dbus::Session,
+    drm::{object_id, DisplayId},
+    peer::Peer,
+    Error,
+};
+
+use super::{Distributor, SeatId};
+
+const DEVICE_VERSION: u32 = 1;
+
+pub struct WaylandBridge {
+    /// Taken out while dispatching, so the handlers can borrow the distributor.
+    display: Option<Display<Distributor>>,
+    handle: DisplayHandle,
+    socket: ListeningSocket,
+    globals: HashMap<PathBuf, GlobalId>,
+    devices: Vec<BoundDevice>,
+    /// The granted leases by the id they are registered with.
+    leases: HashMap<ClientId, WpDrmLeaseV1>,
+}
+
+/// A lease device a client has bound.
+struct BoundDevice {
+    resource: WpDrmLeaseDeviceV1,
+    card_node: PathBuf,
+    seat: SeatId,
+    offers: HashMap<DisplayId, WpDrmLeaseConnectorV1>,
+    /// The initial offers are done, even if there were none.
+    is_synced: bool,
+}
+
+/// A Wayland client along with the identity its leases are held by.
+struct WaylandClient {
+    peer: Peer,
+    session: Session,
+}
+
+impl ClientData for WaylandClient {}
+
+struct ConnectorData {
+    card_node: PathBuf,
+    display: DisplayId,
+}
+
+struct LeaseRequestData {
+    card_node: PathBuf,
+    displays: Mutex<Vec<DisplayId>>,
+}
+
+impl WaylandBridge {
+    /// The socket is a name in `XDG_RUNTIME_DIR`, like `WAYLAND_DISPLAY`, or an absolute path.
+    pub fn new(socket: PathBuf) -> Result<Self, Error> {
+        let display = Display::new()?;
+        let handle = display.handle();
+
+        let socket = if socket.is_absolute() {
+            ListeningSocket::bind_absolute(socket)?
+        } else {
+            ListeningSocket::bind(socket)?
+        };
+
+        Ok(Self {
+            display: Some(display),
+            handle,
+            socket,
+            globals: Default::default(),
+            devices: vec![],
+            leases: Default::default(),
+        })
+    }
+
+    pub fn socket_fd(&self) -> RawFd {
+        self.socket.as_raw_fd()
+    }
+
+    pub fn clients_fd(&self) -> RawFd {
+        self.display
+            .as_ref()
+            .expect("The display is only taken out while dispatching")
+            .as_fd()
+            .as_raw_fd()
+    }
+
+    /// Tells the holder that its lease is gone.
+    pub fn finish_lease(&mut self, lease_id: ClientId) {
+        if let Some(lease) = self.leases.remove(&lease_id) {
+            lease.finished();
+        }
+    }
+}
+
+impl Distributor {
+    pub fn serve_wayland(&mut self, socket: PathBuf) -> Result<(), Error> {
+        info!(
+            "Serving the DRM lease protocol on the Wayland socket {}",
+            socket.display()
+        );
+        self.wayland = Some(WaylandBridge::new(socket)?);
+
+        Ok(())
+    }
+
+    pub(super) fn accept_wayland_clients(&mut self) {
+        loop {
+            let Some(bridge) = &self.wayland else {
+                return;
+            };
+
+            let stream = match bridge.socket.accept() {
+                Ok(Some(stream)) => stream,
+                Ok(None) => return,
+                Err(err) => {
+                    error!("Unable to accept a Wayland client: {err}");
+                    return;
+                }
+            };
+
+            let client = Peer::from_stream(&stream).and_then(|peer| {
+                let session = self.peer_session(&peer)?;
+                Ok(WaylandClient { peer, session })
+            });
+
+            let client = match client {
+                Ok(client) => client,
+                Err(err) => {
+                    error!("Unable to handle a Wayland client: {err}");
+                    continue;
+                }
+            };
+
+            let pid = client.peer.pid();
+            let Some(bridge) = &mut self.wayland else {
+                return;
+            };
+
+            if let Err(err) = bridge.handle.insert_client(stream, Arc::new(client)) {
+                error!("Unable to handle a Wayland client (pid: {pid}): {err}");
+            }
+        }
+    }
+
+    pub(super) fn dispatch_wayland_clients(&mut self) {
+        let Some(mut display) = self
+            .wayland
+            .as_mut()
+            .and_then(|bridge| bridge.display.take())
+        else {
+            return;
+        };
+
+        if let Err(err) = display.dispatch_clients(self) {
+            error!("Unable to dispatch the Wayland clients: {err}");
+        }
+
+        if let Some(bridge) = &mut self.wayland {
+            bridge.display = Some(display);
+        }
+    }
+
+    /// Brings the globals and the connector offers up to date and sends out the events.
+    pub(super) fn sync_wayland(&mut self) {
+        let Some(mut bridge) = self.wayland.take() else {
+            return;
+        };
+
+        self.sync_wayland_globals(&mut bridge);
+        for device in bridge.devices.iter_mut() {
+            self.sync_offers(&bridge.handle, device);
+        }
+
+        if let Some(display) = &mut bridge.display {
+            if let Err(err) = display.flush_clients() {
+                error!("Unable to flush the Wayland clients: {err}");
+            }
+        }
+
+        self.wayland = Some(bridge);
+    }
+
+    /// A lease device global per card.
+    fn sync_wayland_globals(&self, bridge: &mut WaylandBridge) {
+        let handle = bridge.handle.clone();
+
+        bridge.globals.retain(|card_node, global| {
+            let is_present = self.cards.contains_key(card_node);
+            if !is_present {
+                handle.disable_global::<Distributor>(global.clone());
+            }
+
+            is_present
+        });
+
+        for card_node in self.cards.keys() {
+            bridge.globals.entry(card_node.clone()).or_insert_with(|| {
+                handle.create_global::<Distributor, WpDrmLeaseDeviceV1, _>(
+                    DEVICE_VERSION,
+                    card_node.clone(),
+                )
+            });
+        }
+    }
+
+    /// Offers the free displays of the device seat and withdraws the rest.
+    fn sync_offers(&self, handle: &DisplayHandle, device: &mut BoundDevice) {
+        let available = self
+            .cards
+            .get(&device.card_node)
+            .map(|card| card.seat_displays(&device.seat))
+            .unwrap_or_default();
+
+        let is_offered = |display: &DisplayId| {
+            available.contains(display) && !self.leases.is_leased(&device.card_node, display)
+        };
+
+        let mut is_changed = false;
+        device.offers.retain(|display, connector| {
+            let is_kept = is_offered(display);
+            if !is_kept {
+                connector.withdrawn();
+                is_changed = true;
+            }
+
+            is_kept
+        });
+
+        for display in available.iter().filter(|display| is_offered(display)) {
+            if device.offers.contains_key(display) {
+                continue;
+            }
+
+            match self.offer_connector(handle, device, *display) {
+                Ok(connector) => {
+                    device.offers.insert(*display, connector);
+                    is_changed = true;
+                }
+                Err(err) => warn!(
+                    "Unable to offer the display {}/{display}: {err}",
+                    device.card_node.display(),
+                ),
+            }
+        }
+
+        // A freshly bound device gets its `done` even without anything to offer.
+        if is_changed || !device.is_synced {
+            device.resource.done();
+            device.is_synced = true;
+        }
+    }
+
+    fn offer_connector(
+        &self,
+        handle: &DisplayHandle,
+        device: &BoundDevice,
+        display: DisplayId,
+    ) -> Result<WpDrmLeaseConnectorV1, Error> {
+        let Some(card) = self.cards.get(&device.card_node) else {
+            return Err(Error::NoDisplays);
+        };
+
+        let Some(connector_handle) = card.connector_handle(&display)? else {
+            return Err(Error::NoDisplays);
+        };
+
+        let client = handle
+            .get_client(device.resource.id())
+            .map_err(|_| Error::PeerGone)?;
+        let connector = client
+            .create_resource::<WpDrmLeaseConnectorV1, _, Distributor>(
+                handle,
+                device.resource.version(),
+                ConnectorData {
+                    card_node: device.card_node.clone(),
+                    display,
+                },
+            )
+            .map_err(|_| Error::PeerGone)?;
+
+        device.resource.connector(&connector);
+        connector.name(display.to_string());
+        connector.description(format!("{display} on {}", card.info().sysname));
+        connector.connector_id(object_id(connector_handle));
+        connector.done();
+
+        Ok(connector)
+    }
+
+    fn submit_wayland_lease(
+        &mut self,
+        client: &WaylandClient,
+        card_node: &PathBuf,
+        displays: Vec<DisplayId>,
+        lease_id: ClientId,
+        resource: WpDrmLeaseV1,
+    ) {
+        let Some(card) = self.cards.get(card_node) else {
+            resource.finished();
+            return;
+        };
+
+        // Named the way the native clients name them, so the same seat checks apply.
+        let sysname = &card.info().sysname;
+        let requested: Vec<_> = displays
+            .iter()
+            .map(|display| {
+                display_distributor::DisplayId::Connector(format!["{sysname}-{display}"])
+            })
+            .collect();
+
+        self.revoke_dead_holder_leases(&client.session.seat);
+
+        let lease = self
+            .select_displays(&client.session.seat, &requested)
+            .and_then(|selection| {
+                self.create_lease(
+                    &client.session,
+                    &client.peer,
+                    lease_id,
+                    selection,
+                    LeaseMode::AllOrNothing,
+                )
+            });
+
+        match lease {
+            Ok(lease) => {
+                for fd in lease.fds() {
+                    resource.lease_fd(fd);
+                }
+
+                info!(
+                    "Leased {} displays to the Wayland client of the Seat \"{}\" (pid: {})",
+                    card_node.display(),
+                    client.session.seat,
+                    client.peer.pid(),
+                );
+
+                self.leases.register(client.session.seat.clone(), lease);
+                if let Some(bridge) = &mut self.wayland {
+                    bridge.leases.insert(lease_id, resource);
+                }
+            }
+            Err(err) => {
+                info!(
+                    "Unable to lease displays to the Wayland client of the Seat \"{}\" (pid: {}): {err}",
+                    client.session.seat,
+                    client.peer.pid(),
+                );
+
+                resource.finished();
+            }
+        }
+    }
+
+    fn destroy_wayland_lease(&mut self, lease_id: ClientId) {
+        if let Some(bridge) = &mut self.wayland {
+            bridge.leases.remove(&lease_id);
+        }
+
+        for lease in self.leases.unregister_of_client(lease_id) {
+            info!(
+                "The Wayland client (pid: {}) has destroyed its lease, revoking it",
+                lease.holder.pid(),
+            );
+
+            self.revoke_lease(&lease);
+        }
+    }
+}
+
+impl GlobalDispatch<WpDrmLeaseDeviceV1, PathBuf> for Distributor {
+    fn bind(
+        state: &mut Self,
+        _handle: &DisplayHandle,
+        client: &Client,
+        resource: New<WpDrmLeaseDeviceV1>,
+        card_node: &PathBuf,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        let resource = data_init.init(resource, card_node.clone());
+
+        let Some(client) = client.get_data::<WaylandClient>() else {
+            return;
+        };
+
+        // A fresh fd of a card that already has a master is not a master, as the protocol requires.
+        match File::options().read(true).write(true).open(card_node) {
+            Ok(card) => resource.drm_fd(card.as_fd()),
+            Err(err) => error!("Unable to open {}: {err}", card_node.display()),
+        }
+
+        // The connectors are offered on the next sync.
+        if let Some(bridge) = &mut state.wayland {
+            bridge.devices.push(BoundDevice {
+                resource,
+                card_node: card_node.clone(),
+                seat: client.session.seat.clone(),
+                offers: Default::default(),
+                is_synced: false,
+            });
+        }
+    }
+}
+
+impl Dispatch<WpDrmLeaseDeviceV1, PathBuf> for Distributor {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        resource: &WpDrmLeaseDeviceV1,
+        request: wp_drm_lease_device_v1::Request,
+        card_node: &PathBuf,
+        _handle: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        match request {
+            wp_drm_lease_device_v1::Request::CreateLeaseRequest { id } => {
+                data_init.init(
+                    id,
+                    LeaseRequestData {
+                        card_node: card_node.clone(),
+                        displays: Default::default(),
+                    },
+                );
+            }
+            wp_drm_lease_device_v1::Request::Release => resource.released(),
+            _ => {}
+        }
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: wayland_server::backend::ClientId,
+        resource: &WpDrmLeaseDeviceV1,
+        _card_node: &PathBuf,
+    ) {
+        if let Some(bridge) = &mut state.wayland {
+            bridge
+                .devices
+                .retain(|device| device.resource.id() != resource.id());
+        }
+    }
+}
+
+impl Dispatch<WpDrmLeaseConnectorV1, ConnectorData> for Distributor {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &WpDrmLeaseConnectorV1,
+        _request: wp_drm_lease_connector_v1::Request,
+        _data: &ConnectorData,
+        _handle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        // The only request is `destroy`, which is handled by `destroyed`.
+    }
+
+    fn destroyed(
+        state: &mut Self,
+        _client: wayland_server::backend::ClientId,
+        resource: &WpDrmLeaseConnectorV1,
+        data: &ConnectorData,
+    ) {
+        let Some(bridge) = &mut state.wayland else {
+            return;
+        };
+
+        for device in bridge.devices.iter_mut() {
+            if device
+                .offers
+                .get(&data.display)
+                .is_some_and(|connector| connector.id() == resource.id())
+            {
+                device.offers.remove(&data.display);
+            }
+        }
+    }
+}
+
+impl Dispatch<WpDrmLeaseRequestV1, LeaseRequestData> for Distributor {
+    fn request(
+        state: &mut Self,
+        client: &Client,
+        resource: &WpDrmLeaseRequestV1,
+        request: wp_drm_lease_request_v1::Request,
+        data: &LeaseRequestData,
+        _handle: &DisplayHandle,
+        data_init: &mut DataInit<'_, Self>,
+    ) {
+        let mut displays = data.displays.lock().expect("The lock is never poisoned");
+
+        match request {
+            wp_drm_lease_request_v1::Request::RequestConnector { connector } => {
+                let Some(connector) = connector.data::<ConnectorData>() else {
+                    return;
+                };
+
+                if connector.card_node != data.card_node {
+                    resource.post_error(
+                        wp_drm_lease_request_v1::Error::WrongDevice,
+                        "The connector belongs to another lease device",
+                    );
+                } else if displays.contains(&connector.display) {
+                    resource.post_error(
+                        wp_drm_lease_request_v1::Error::DuplicateConnector,
+                        "The connector is already requested",
+                    );
+                } else {
+                    displays.push(connector.display);
+                }
+            }
+            wp_drm_lease_request_v1::Request::Submit { id } => {
+                let lease_id = state.next_client_id;
+                state.next_client_id += 1;
+
+                let lease = data_init.init(id, lease_id);
+                if displays.is_empty() {
+                    resource.post_error(
+                        wp_drm_lease_request_v1::Error::EmptyLease,
+                        "No connector is requested",
+                    );
+                    return;
+                }
+
+                let Some(client) = client.get_data::<WaylandClient>() else {
+                    lease.finished();
+                    return;
+                };
+
+                state.submit_wayland_lease(
+                    client,
+                    &data.card_node,
+                    mem::take(&mut *displays),
+                    lease_id,
+                    lease,
+                );
+            }
+            _ => {}
+        }
+    }
+}
+
+impl Dispatch<WpDrmLeaseV1, ClientId> for Distributor {
+    fn request(
+        _state: &mut Self,
+        _client: &Client,
+        _resource: &WpDrmLeaseV1,
+        _request: wp_drm_lease_v1::Request,
+        _lease_id: &ClientId,
+        _handle: &DisplayHandle,
+        _data_init: &mut DataInit<'_, Self>,
+    ) {
+        // The only request is `destroy`, which is handled by `destroyed`.
+    }
+
+    /// Destroying the lease object, or disconnecting, revokes the lease.
+    fn destroyed(
+        state: &mut Self,
+        _client: wayland_server::backend::ClientId,
+        _resource: &WpDrmLeaseV1,
+        lease_id: &ClientId,
+    ) {
+        state.destroy_wayland_lease(*lease_id);
+    }
+}
@@ -0,0 +1,144 @@
+use std::{fmt, str::FromStr};
+
+use drm::control::connector::Interface;
+
+use crate::Error;
+
+type InterfaceId = u32;
+
+/// The connector type names the kernel uses in the connector names,
+/// see `drm_connector_enum_list` in `drivers/gpu/drm/drm_connector.c`.
+const INTERFACE_NAMES: &[(Interface, &str)] = &[
+    (Interface::Unknown, "Unknown"),
+    (Interface::VGA, "VGA"),
+    (Interface::DVII, "DVI-I"),
+    (Interface::DVID, "DVI-D"),
+    (Interface::DVIA, "DVI-A"),
+    (Interface::Composite, "Composite"),
+    (Interface::SVideo, "SVIDEO"),
+    (Interface::LVDS, "LVDS"),
+    (Interface::Component, "Component"),
+    (Interface::NinePinDIN, "DIN"),
+    (Interface::DisplayPort, "DP"),
+    (Interface::HDMIA, "HDMI-A"),
+    (Interface::HDMIB, "HDMI-B"),
+    (Interface::TV, "TV"),
+    (Interface::EmbeddedDisplayPort, "eDP"),
+    (Interface::Virtual, "Virtual"),
+    (Interface::DSI, "DSI"),
+    (Interface::DPI, "DPI"),
+    (Interface::Writeback, "Writeback"),
+    (Interface::SPI, "SPI"),
+    (Interface::USB, "USB"),
+];
+
+pub fn interface_name(iface: Interface) -> &'static str {
+    INTERFACE_NAMES
+        .iter()
+        .find_map(|(known_iface, name)| (*known_iface == iface).then_some(*name))
+        .unwrap_or("Unknown")
+}
+
+pub fn parse_interface(name: &str) -> Option<Interface> {
+    INTERFACE_NAMES
+        .iter()
+        .find_map(|(iface, known_name)| (*known_name == name).then_some(*iface))
+}
+
+/// A connector name in the kernel's `<interface>-<id>` form, e.g. `HDMI-A-1`.
+///
+/// The interface names can contain dashes themselves,
+/// so the id is always the part after the last dash.
+#[derive(Debug, Clone, Copy, Hash, PartialEq, Eq)]
+pub struct DisplayId(pub Interface, pub InterfaceId);
+
+impl fmt::Display for DisplayId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}-{}", interface_name(self.0), self.1)
+    }
+}
+
+impl FromStr for DisplayId {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let parse_error = || Error::UnableToParseDisplayId(value.to_string());
+
+        let (iface, iface_id) = value.rsplit_once('-').ok_or_else(parse_error)?;
+
+        let iface = parse_interface(iface).ok_or_else(parse_error)?;
+        let iface_id = iface_id.parse::<InterfaceId>().map_err(|_| parse_error())?;
+
+        Ok(Self(iface, iface_id))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interface_names_round_trip() {
+        for (iface, name) in INTERFACE_NAMES {
+            assert_eq!(interface_name(*iface), *name);
+            assert_eq!(parse_interface(name), Some(*iface), "{name}");
+        }
+    }
+
+    #[test]
+    fn parses_connector_names() {
+        let cases = [
+            ("VGA-1", DisplayId(Interface::VGA, 1)),
+            ("DVI-I-2", DisplayId(Interface::DVII, 2)),
+            ("DVI-D-1", DisplayId(Interface::DVID, 1)),
+            ("DVI-A-3", DisplayId(Interface::DVIA, 3)),
+            ("HDMI-A-1", DisplayId(Interface::HDMIA, 1)),
+            ("HDMI-B-2", DisplayId(Interface::HDMIB, 2)),
+            ("DP-1", DisplayId(Interface::DisplayPort, 1)),
+            ("DP-12", DisplayId(Interface::DisplayPort, 12)),
+            ("eDP-1", DisplayId(Interface::EmbeddedDisplayPort, 1)),
+            ("SVIDEO-1", DisplayId(Interface::SVideo, 1)),
+            ("DIN-1", DisplayId(Interface::NinePinDIN, 1)),
+            ("Virtual-1", DisplayId(Interface::Virtual, 1)),
+            ("Writeback-1", DisplayId(Interface::Writeback, 1)),
+            ("Unknown-4", DisplayId(Interface::Unknown, 4)),
+        ];
+
+        for (name, display_id) in cases {
+            assert_eq!(name.parse::<DisplayId>().unwrap(), display_id, "{name}");
+            assert_eq!(display_id.to_string(), name);
+        }
+    }
+
+    #[test]
+    fn rejects_malformed_connector_names() {
+        let cases = [
+            "",
+            "HDMI",
+            "HDMI-A",
+            "HDMI-A-",
+            "HDMI-A-x",
+            "HDMI-C-1",
+            "HDMIA-1",
+            "hdmi-a-1",
+            "-1",
+            "DP--1",
+            "DP-1-",
+            "DP-4294967296",
+        ];
+
+        for name in cases {
+            assert!(name.parse::<DisplayId>().is_err(), "{name}");
+        }
+    }
+
+    #[test]
+    fn every_interface_round_trips_through_display_id() {
+        for (iface, _) in INTERFACE_NAMES {
+            let display_id = DisplayId(*iface, 7);
+            let name = display_id.to_string();
+
+            assert_eq!(name.parse::<DisplayId>().unwrap(), display_id, "{name}");
+        }
+    }
+}
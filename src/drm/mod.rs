@@ -0,0 +1,387 @@
+use std::{
+    collections::{HashMap, HashSet},
+    fs::File,
+    os::fd::{AsFd, BorrowedFd, FromRawFd, OwnedFd},
+    path::Path,
+};
+
+use display_distributor::{CardInfo, EdidId, PciInfo};
+use drm::{
+    self,
+    control::{
+        connector::{self as drm_connector, Info as ConnectorInfo},
+        crtc,
+        lease::LesseeId,
+        plane as drm_plane, property, Device, DrmLeaseCreateResult, RawResourceHandle,
+        ResourceHandle,
+    },
+    ClientCapability, Device as _,
+};
+use nix::fcntl::OFlag;
+
+use crate::{distributor::SeatId, Error};
+
+pub use connector::DisplayId;
+pub use plane::PlanePolicy;
+
+pub mod connector;
+pub mod plane;
+pub mod routing;
+
+pub struct Card {
+    info: CardInfo,
+    file: File,
+    plane_policy: PlanePolicy,
+    displays: HashMap<SeatId, HashSet<DisplayId>>,
+    leased_objects: HashMap<u32, HashSet<RawResourceHandle>>,
+}
+
+/// The resources of a freshly created DRM lease.
+pub struct DisplaysLease {
+    pub fd: OwnedFd,
+    pub lessee_id: LesseeId,
+    pub connectors: Vec<(drm_connector::Handle, DisplayId)>,
+    pub crtcs: Vec<crtc::Handle>,
+    pub planes: Vec<drm_plane::Handle>,
+}
+
+impl Card {
+    pub fn new(dev: &udev::Device, plane_policy: PlanePolicy) -> Result<Self, Error> {
+        let node = dev.devnode().expect("GPU must have a node");
+
+        let card = Self {
+            info: card_info(dev, node),
+            file: File::open(node)?,
+            plane_policy,
+            displays: Default::default(),
+            leased_objects: Default::default(),
+        };
+
+        // Without it the kernel hides the primary and cursor planes.
+        card.set_client_capability(ClientCapability::UniversalPlanes, true)?;
+
+        Ok(card)
+    }
+
+    pub fn info(&self) -> &CardInfo {
+        &self.info
+    }
+
+    pub fn add_seat_display(&mut self, seat: SeatId, display: DisplayId) {
+        self.displays.entry(seat).or_default().insert(display);
+    }
+
+    pub fn remove_display(&mut self, display: &DisplayId) {
+        for displays in self.displays.values_mut() {
+            displays.remove(display);
+        }
+
+        self.displays.retain(|_, displays| !displays.is_empty());
+    }
+
+    pub fn clear_displays(&mut self) {
+        self.displays.clear();
+    }
+
+    /// Returns whether the card had any displays of the seat.
+    pub fn remove_seat_displays(&mut self, seat: &SeatId) -> bool {
+        self.displays.remove(seat).is_some()
+    }
+
+    pub fn has_displays(&self) -> bool {
+        !self.displays.is_empty()
+    }
+
+    pub fn seat_displays(&self, seat: &SeatId) -> HashSet<DisplayId> {
+        self.displays.get(seat).cloned().unwrap_or_default()
+    }
+
+    pub fn displays(&self) -> impl Iterator<Item = DisplayId> + '_ {
+        self.displays.values().flatten().copied()
+    }
+
+    pub fn display_seat(&self, display: &DisplayId) -> Option<&SeatId> {
+        self.displays
+            .iter()
+            .find_map(|(seat, displays)| displays.contains(display).then_some(seat))
+    }
+
+    /// Finds a connector of this card by its kernel name.
+    ///
+    /// The name can be prefixed with the card name, e.g. `card0-DP-1`,
+    /// in which case the connectors of other cards never match.
+    pub fn find_connector(&self, name: &str) -> Result<Option<DisplayId>, Error> {
+        let name = name
+            .strip_prefix(&self.info.sysname)
+            .and_then(|name| name.strip_prefix('-'))
+            .unwrap_or(name);
+
+        let Ok(display_id) = name.parse::<DisplayId>() else {
+            return Ok(None);
+        };
+
+        let exists = self
+            .connectors()?
+            .iter()
+            .any(|connector| connector_display_id(connector) == display_id);
+
+        Ok(exists.then_some(display_id))
+    }
+
+    /// The DRM handle of the connector driving the display.
+    #[cfg(feature = "wayland")]
+    pub fn connector_handle(
+        &self,
+        display: &DisplayId,
+    ) -> Result<Option<drm_connector::Handle>, Error> {
+        Ok(self
+            .connectors()?
+            .iter()
+            .find(|connector| connector_display_id(connector) == *display)
+            .map(ConnectorInfo::handle))
+    }
+
+    /// Finds the connectors of this card the monitor with the given EDID is plugged into.
+    pub fn find_edid(&self, edid_id: &EdidId) -> Result<Vec<DisplayId>, Error> {
+        let mut found = vec![];
+
+        for connector in self.connectors()? {
+            let Some(edid_blob) = self.property_value(connector.handle(), "EDID")? else {
+                continue;
+            };
+
+            if edid_blob == 0 {
+                continue;
+            }
+
+            let edid = self.get_property_blob(edid_blob)?;
+            if EdidId::from_edid(&edid).as_ref() == Some(edid_id) {
+                found.push(connector_display_id(&connector));
+            }
+        }
+
+        Ok(found)
+    }
+
+    pub fn lease_displays(
+        &mut self,
+        displays: &HashSet<DisplayId>,
+    ) -> Result<DisplaysLease, Error> {
+        if displays.is_empty() {
+            return Err(Error::NoDisplays);
+        }
+
+        let resources = self.resource_handles()?;
+
+        let mut connectors = vec![];
+        let mut routes = vec![];
+        let mut busy_crtcs = HashSet::new();
+        for connector_handle in resources.connectors() {
+            let connector = self.get_connector(*connector_handle, true)?;
+
+            let display_id = connector_display_id(&connector);
+            let current_crtc = self.connector_crtc(&connector)?;
+
+            // The CRTC keeps driving the display that stays with the seat.
+            if !displays.contains(&display_id) {
+                busy_crtcs.extend(current_crtc);
+                continue;
+            }
+
+            let mut possible_crtcs = vec![];
+            for encoder_handle in connector.encoders() {
+                let encoder = self.get_encoder(*encoder_handle)?;
+
+                for crtc_handle in resources.filter_crtcs(encoder.possible_crtcs()) {
+                    if !possible_crtcs.contains(&crtc_handle) {
+                        possible_crtcs.push(crtc_handle);
+                    }
+                }
+            }
+
+            connectors.push((*connector_handle, display_id));
+            routes.push(routing::CrtcRoute {
+                display: display_id,
+                current_crtc,
+                possible_crtcs,
+            });
+        }
+
+        let crtcs = routing::route_crtcs(&routes, |crtc| {
+            busy_crtcs.contains(&crtc) || self.is_leased_object(crtc.into())
+        })?;
+
+        let planes = plane::select_planes(
+            self.plane_policy,
+            &crtcs,
+            &self.plane_candidates()?,
+            &busy_crtcs,
+            |plane| self.is_leased_object(plane.into()),
+        )?;
+
+        let resources: HashSet<RawResourceHandle> = connectors
+            .iter()
+            .map(|(handle, _)| (*handle).into())
+            .chain(crtcs.iter().map(|handle| (*handle).into()))
+            .chain(planes.iter().map(|handle| (*handle).into()))
+            .collect();
+
+        let DrmLeaseCreateResult { fd, lessee_id } = self.create_lease(
+            &resources.iter().copied().collect::<Vec<_>>(),
+            OFlag::O_CLOEXEC | OFlag::O_NONBLOCK,
+        )?;
+
+        // SAFETY: the lease fd is freshly created by the kernel and is owned by nobody else.
+        let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+        self.leased_objects.insert(u32::from(lessee_id), resources);
+
+        Ok(DisplaysLease {
+            fd,
+            lessee_id,
+            connectors,
+            crtcs,
+            planes,
+        })
+    }
+
+    pub fn revoke_displays(&mut self, lessee_id: LesseeId) -> Result<(), Error> {
+        self.revoke_lease(lessee_id)?;
+        self.leased_objects.remove(&u32::from(lessee_id));
+
+        Ok(())
+    }
+
+    fn is_leased_object(&self, object: RawResourceHandle) -> bool {
+        self.leased_objects
+            .values()
+            .any(|objects| objects.contains(&object))
+    }
+
+    /// The CRTC currently driving the connector, if any.
+    fn connector_crtc(&self, connector: &ConnectorInfo) -> Result<Option<crtc::Handle>, Error> {
+        let Some(encoder_handle) = connector.current_encoder() else {
+            return Ok(None);
+        };
+
+        Ok(self.get_encoder(encoder_handle)?.crtc())
+    }
+
+    fn plane_candidates(&self) -> Result<Vec<plane::PlaneCandidate>, Error> {
+        let resources = self.resource_handles()?;
+
+        let mut candidates = vec![];
+        for plane_handle in self.plane_handles()?.planes() {
+            let Some(kind) = self
+                .property_value(*plane_handle, "type")?
+                .and_then(plane::PlaneType::from_raw)
+            else {
+                continue;
+            };
+
+            let plane = self.get_plane(*plane_handle)?;
+            candidates.push(plane::PlaneCandidate {
+                handle: *plane_handle,
+                kind,
+                possible_crtcs: resources.filter_crtcs(plane.possible_crtcs()),
+                current_crtc: plane.crtc(),
+            });
+        }
+
+        Ok(candidates)
+    }
+
+    /// The current connector states, without probing the outputs.
+    fn connectors(&self) -> Result<Vec<ConnectorInfo>, Error> {
+        let mut connectors = vec![];
+        for connector_handle in self.resource_handles()?.connectors() {
+            connectors.push(self.get_connector(*connector_handle, false)?);
+        }
+
+        Ok(connectors)
+    }
+
+    fn property_value<H: ResourceHandle>(
+        &self,
+        handle: H,
+        name: &str,
+    ) -> Result<Option<property::RawValue>, Error> {
+        let properties = self.get_properties(handle)?;
+        let (property_handles, values) = properties.as_props_and_values();
+
+        for (property_handle, value) in property_handles.iter().zip(values) {
+            let property = self.get_property(*property_handle)?;
+            if property.name().to_bytes() == name.as_bytes() {
+                return Ok(Some(*value));
+            }
+        }
+
+        Ok(None)
+    }
+}
+
+fn connector_display_id(connector: &ConnectorInfo) -> DisplayId {
+    DisplayId(connector.interface(), connector.interface_id())
+}
+
+/// The DRM object id to report to clients.
+pub fn object_id<H: Into<RawResourceHandle>>(handle: H) -> u32 {
+    handle.into().get()
+}
+
+fn card_info(dev: &udev::Device, node: &Path) -> CardInfo {
+    // The card itself is a class device, the driver is bound to its parent.
+    let parent = dev.parent();
+
+    let driver = parent
+        .as_ref()
+        .and_then(|parent| parent.driver())
+        .map(|driver| driver.to_string_lossy().to_string());
+
+    let pci = parent.as_ref().and_then(pci_info);
+
+    CardInfo {
+        devnode: node.to_path_buf(),
+        sysname: dev.sysname().to_string_lossy().to_string(),
+        driver,
+        pci,
+    }
+}
+
+fn pci_info(dev: &udev::Device) -> Option<PciInfo> {
+    let property = |name| {
+        dev.property_value(name)
+            .map(|value| value.to_string_lossy().to_string())
+    };
+
+    // Both are `<vendor>:<device>` in hex, e.g. `1002:73BF`.
+    let parse_ids = |ids: String| {
+        let (vendor, device) = ids.split_once(':')?;
+        Some((
+            u16::from_str_radix(vendor, 16).ok()?,
+            u16::from_str_radix(device, 16).ok()?,
+        ))
+    };
+
+    let slot = property("PCI_SLOT_NAME")?;
+    let (vendor_id, device_id) = property("PCI_ID").and_then(parse_ids)?;
+    let subsystem_ids = property("PCI_SUBSYS_ID").and_then(parse_ids);
+
+    Some(PciInfo {
+        slot,
+        vendor_id,
+        device_id,
+        subsystem_vendor_id: subsystem_ids.map(|(vendor, _)| vendor),
+        subsystem_device_id: subsystem_ids.map(|(_, device)| device),
+    })
+}
+
+impl AsFd for Card {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.file.as_fd()
+    }
+}
+
+impl drm::Device for Card {}
+
+impl Device for Card {}
@@ -0,0 +1,266 @@
+use std::collections::HashSet;
+
+use clap::ValueEnum;
+use drm::control::{crtc, plane};
+
+use crate::Error;
+
+use super::object_id;
+
+/// Which planes of a leased CRTC go into the lease.
+///
+/// The primary plane is always leased, a CRTC can't scan out anything without it.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum PlanePolicy {
+    /// Only the primary plane.
+    Primary,
+
+    /// The primary plane and the cursor plane, if the CRTC has one.
+    #[default]
+    Cursor,
+
+    /// The primary, the cursor and every overlay plane the CRTC can use.
+    All,
+}
+
+/// The values of the `type` plane property.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PlaneType {
+    Overlay,
+    Primary,
+    Cursor,
+}
+
+impl PlaneType {
+    pub fn from_raw(value: u64) -> Option<Self> {
+        match value {
+            0 => Some(Self::Overlay),
+            1 => Some(Self::Primary),
+            2 => Some(Self::Cursor),
+            _ => None,
+        }
+    }
+}
+
+pub struct PlaneCandidate {
+    pub handle: plane::Handle,
+    pub kind: PlaneType,
+    pub possible_crtcs: Vec<crtc::Handle>,
+    pub current_crtc: Option<crtc::Handle>,
+}
+
+impl PlaneCandidate {
+    fn fits(&self, kind: PlaneType, crtc: crtc::Handle) -> bool {
+        self.kind == kind && self.possible_crtcs.contains(&crtc)
+    }
+}
+
+/// Picks the planes to lease along with the CRTCs.
+///
+/// A plane already bound to a CRTC is preferred for it,
+/// and no plane is picked twice or picked when it is `unavailable`.
+/// The planes bound to the `busy_crtcs` are never picked, they scan out what stays with the seat.
+pub fn select_planes(
+    policy: PlanePolicy,
+    crtcs: &[crtc::Handle],
+    candidates: &[PlaneCandidate],
+    busy_crtcs: &HashSet<crtc::Handle>,
+    unavailable: impl Fn(plane::Handle) -> bool,
+) -> Result<Vec<plane::Handle>, Error> {
+    let mut taken = HashSet::new();
+    let mut selected = vec![];
+
+    let pick = |kind, crtc, taken: &mut HashSet<plane::Handle>| {
+        let plane = candidates
+            .iter()
+            .filter(|candidate| candidate.fits(kind, crtc))
+            .filter(|candidate| {
+                !taken.contains(&candidate.handle) && !unavailable(candidate.handle)
+            })
+            .filter(|candidate| {
+                !candidate
+                    .current_crtc
+                    .is_some_and(|current_crtc| busy_crtcs.contains(&current_crtc))
+            })
+            .min_by_key(|candidate| candidate.current_crtc != Some(crtc))?;
+
+        taken.insert(plane.handle);
+        Some(plane.handle)
+    };
+
+    for crtc in crtcs.iter().copied() {
+        let primary = pick(PlaneType::Primary, crtc, &mut taken)
+            .ok_or(Error::NoPrimaryPlane(object_id(crtc)))?;
+        selected.push(primary);
+
+        if policy == PlanePolicy::Primary {
+            continue;
+        }
+
+        selected.extend(pick(PlaneType::Cursor, crtc, &mut taken));
+
+        if policy == PlanePolicy::All {
+            while let Some(overlay) = pick(PlaneType::Overlay, crtc, &mut taken) {
+                selected.push(overlay);
+            }
+        }
+    }
+
+    Ok(selected)
+}
+
+#[cfg(test)]
+mod tests {
+    use drm::control::RawResourceHandle;
+
+    use super::*;
+
+    fn handle<H: From<RawResourceHandle>>(id: u32) -> H {
+        RawResourceHandle::new(id).unwrap().into()
+    }
+
+    fn candidate(
+        id: u32,
+        kind: PlaneType,
+        possible_crtcs: &[u32],
+        current_crtc: Option<u32>,
+    ) -> PlaneCandidate {
+        PlaneCandidate {
+            handle: handle(id),
+            kind,
+            possible_crtcs: possible_crtcs.iter().copied().map(handle).collect(),
+            current_crtc: current_crtc.map(handle),
+        }
+    }
+
+    fn select(
+        policy: PlanePolicy,
+        crtcs: &[u32],
+        candidates: &[PlaneCandidate],
+        unavailable: &[u32],
+    ) -> Result<Vec<plane::Handle>, Error> {
+        select_with_busy_crtcs(policy, crtcs, candidates, &[], unavailable)
+    }
+
+    fn select_with_busy_crtcs(
+        policy: PlanePolicy,
+        crtcs: &[u32],
+        candidates: &[PlaneCandidate],
+        busy_crtcs: &[u32],
+        unavailable: &[u32],
+    ) -> Result<Vec<plane::Handle>, Error> {
+        let crtcs: Vec<crtc::Handle> = crtcs.iter().copied().map(handle).collect();
+        let busy_crtcs = busy_crtcs.iter().copied().map(handle).collect();
+
+        select_planes(policy, &crtcs, candidates, &busy_crtcs, |plane| {
+            unavailable
+                .iter()
+                .any(|id| handle::<plane::Handle>(*id) == plane)
+        })
+    }
+
+    fn planes(ids: &[u32]) -> Vec<plane::Handle> {
+        ids.iter().copied().map(handle).collect()
+    }
+
+    #[test]
+    fn selects_planes_by_policy() {
+        let candidates = [
+            candidate(10, PlaneType::Primary, &[1], Some(1)),
+            candidate(11, PlaneType::Cursor, &[1], None),
+            candidate(12, PlaneType::Overlay, &[1], None),
+            candidate(13, PlaneType::Overlay, &[1], None),
+        ];
+
+        let cases = [
+            (PlanePolicy::Primary, planes(&[10])),
+            (PlanePolicy::Cursor, planes(&[10, 11])),
+            (PlanePolicy::All, planes(&[10, 11, 12, 13])),
+        ];
+
+        for (policy, expected) in cases {
+            let selected = select(policy, &[1], &candidates, &[]).unwrap();
+            assert_eq!(selected, expected, "{policy:?}");
+        }
+    }
+
+    #[test]
+    fn missing_cursor_plane_is_skipped() {
+        let candidates = [
+            candidate(10, PlaneType::Primary, &[1], None),
+            candidate(12, PlaneType::Overlay, &[1], None),
+        ];
+
+        let selected = select(PlanePolicy::Cursor, &[1], &candidates, &[]).unwrap();
+        assert_eq!(selected, planes(&[10]));
+
+        let selected = select(PlanePolicy::All, &[1], &candidates, &[]).unwrap();
+        assert_eq!(selected, planes(&[10, 12]));
+    }
+
+    #[test]
+    fn shared_planes_are_picked_once() {
+        // Both primaries and the only cursor can go on either CRTC.
+        let candidates = [
+            candidate(10, PlaneType::Primary, &[1, 2], Some(2)),
+            candidate(20, PlaneType::Primary, &[1, 2], Some(1)),
+            candidate(11, PlaneType::Cursor, &[1, 2], None),
+        ];
+
+        // The primaries stay on the CRTCs they are bound to.
+        let selected = select(PlanePolicy::Cursor, &[1, 2], &candidates, &[]).unwrap();
+        assert_eq!(selected, planes(&[20, 11, 10]));
+    }
+
+    #[test]
+    fn unavailable_planes_are_skipped() {
+        let candidates = [
+            candidate(10, PlaneType::Primary, &[1], Some(1)),
+            candidate(20, PlaneType::Primary, &[1], None),
+            candidate(11, PlaneType::Cursor, &[1], None),
+        ];
+
+        let selected = select(PlanePolicy::Cursor, &[1], &candidates, &[10, 11]).unwrap();
+        assert_eq!(selected, planes(&[20]));
+    }
+
+    #[test]
+    fn planes_of_busy_crtcs_are_skipped() {
+        // CRTC 2 keeps scanning out a display of the seat.
+        let candidates = [
+            candidate(10, PlaneType::Primary, &[1, 2], None),
+            candidate(20, PlaneType::Primary, &[1, 2], Some(2)),
+            candidate(11, PlaneType::Cursor, &[1, 2], Some(2)),
+        ];
+
+        let selected =
+            select_with_busy_crtcs(PlanePolicy::Cursor, &[1], &candidates, &[2], &[]).unwrap();
+        assert_eq!(selected, planes(&[10]));
+    }
+
+    #[test]
+    fn only_plane_bound_to_busy_crtc_fails() {
+        let candidates = [candidate(20, PlaneType::Primary, &[1, 2], Some(2))];
+
+        let result = select_with_busy_crtcs(PlanePolicy::Primary, &[1], &candidates, &[2], &[]);
+        assert!(
+            matches!(result, Err(Error::NoPrimaryPlane(1))),
+            "{result:?}"
+        );
+    }
+
+    #[test]
+    fn crtc_without_primary_plane_fails() {
+        let candidates = [
+            candidate(10, PlaneType::Primary, &[1], Some(1)),
+            candidate(11, PlaneType::Cursor, &[2], None),
+        ];
+
+        // The only primary plane is taken by the first CRTC.
+        let result = select(PlanePolicy::Primary, &[1, 2], &candidates, &[]);
+        assert!(
+            matches!(result, Err(Error::NoPrimaryPlane(2))),
+            "{result:?}"
+        );
+    }
+}
@@ -0,0 +1,180 @@
+use std::collections::{HashMap, HashSet};
+
+use drm::control::crtc;
+
+use crate::Error;
+
+use super::DisplayId;
+
+/// The CRTCs a connector can be driven by.
+pub struct CrtcRoute {
+    pub display: DisplayId,
+    pub current_crtc: Option<crtc::Handle>,
+    /// The union of the `possible_crtcs` of every connector encoder.
+    pub possible_crtcs: Vec<crtc::Handle>,
+}
+
+impl CrtcRoute {
+    /// The current CRTC goes first, so the running mode is kept when possible.
+    fn candidates(&self) -> impl Iterator<Item = crtc::Handle> + '_ {
+        self.current_crtc.into_iter().chain(
+            self.possible_crtcs
+                .iter()
+                .copied()
+                .filter(|crtc| Some(*crtc) != self.current_crtc),
+        )
+    }
+}
+
+/// Assigns a distinct CRTC to every route, in the order of `routes`.
+///
+/// This is a bipartite matching between the connectors and the CRTCs:
+/// a CRTC taken by one connector is handed over to another one
+/// when the first connector can be moved to a different CRTC.
+/// The `unavailable` CRTCs are never assigned.
+pub fn route_crtcs(
+    routes: &[CrtcRoute],
+    unavailable: impl Fn(crtc::Handle) -> bool,
+) -> Result<Vec<crtc::Handle>, Error> {
+    let mut assigned = HashMap::new();
+
+    for (route_index, route) in routes.iter().enumerate() {
+        let mut visited = HashSet::new();
+        if !assign(
+            route_index,
+            routes,
+            &unavailable,
+            &mut assigned,
+            &mut visited,
+        ) {
+            return Err(Error::NoFreeCrtc(route.display));
+        }
+    }
+
+    let mut crtcs = vec![None; routes.len()];
+    for (crtc, route_index) in assigned {
+        crtcs[route_index] = Some(crtc);
+    }
+
+    Ok(crtcs.into_iter().flatten().collect())
+}
+
+/// Looks for an augmenting path starting from the route.
+fn assign(
+    route_index: usize,
+    routes: &[CrtcRoute],
+    unavailable: &impl Fn(crtc::Handle) -> bool,
+    assigned: &mut HashMap<crtc::Handle, usize>,
+    visited: &mut HashSet<crtc::Handle>,
+) -> bool {
+    for crtc in routes[route_index].candidates() {
+        if unavailable(crtc) || !visited.insert(crtc) {
+            continue;
+        }
+
+        let is_free = match assigned.get(&crtc).copied() {
+            None => true,
+            Some(other_index) => assign(other_index, routes, unavailable, assigned, visited),
+        };
+
+        if is_free {
+            assigned.insert(crtc, route_index);
+            return true;
+        }
+    }
+
+    false
+}
+
+#[cfg(test)]
+mod tests {
+    use drm::control::{connector::Interface, RawResourceHandle};
+
+    use super::*;
+
+    fn crtc(id: u32) -> crtc::Handle {
+        RawResourceHandle::new(id).unwrap().into()
+    }
+
+    fn route(id: u32, current_crtc: Option<u32>, possible_crtcs: &[u32]) -> CrtcRoute {
+        CrtcRoute {
+            display: DisplayId(Interface::DisplayPort, id),
+            current_crtc: current_crtc.map(crtc),
+            possible_crtcs: possible_crtcs.iter().copied().map(crtc).collect(),
+        }
+    }
+
+    /// The expected CRTC ids, or the display that can't be routed.
+    type Expected = Result<Vec<u32>, u32>;
+
+    #[test]
+    fn routes_crtcs() {
+        let cases: Vec<(&str, Vec<CrtcRoute>, &[u32], Expected)> = vec![
+            ("no routes", vec![], &[], Ok(vec![])),
+            (
+                "single route",
+                vec![route(1, None, &[1, 2])],
+                &[],
+                Ok(vec![1]),
+            ),
+            (
+                "current CRTC is kept",
+                vec![route(1, Some(2), &[1, 2])],
+                &[],
+                Ok(vec![2]),
+            ),
+            (
+                "greedy fails, augmenting path moves the first route",
+                vec![route(1, None, &[1, 2]), route(2, None, &[1])],
+                &[],
+                Ok(vec![2, 1]),
+            ),
+            (
+                "augmenting path through several routes",
+                vec![
+                    route(1, None, &[1, 2]),
+                    route(2, None, &[2, 3]),
+                    route(3, None, &[1]),
+                ],
+                &[],
+                Ok(vec![2, 3, 1]),
+            ),
+            (
+                "unavailable CRTC is skipped",
+                vec![route(1, Some(1), &[1, 2])],
+                &[1],
+                Ok(vec![2]),
+            ),
+            (
+                "more routes than CRTCs",
+                vec![route(1, None, &[1]), route(2, None, &[1])],
+                &[],
+                Err(2),
+            ),
+            (
+                "only CRTC is unavailable",
+                vec![route(1, None, &[1])],
+                &[1],
+                Err(1),
+            ),
+            ("no possible CRTCs", vec![route(1, None, &[])], &[], Err(1)),
+        ];
+
+        for (name, routes, unavailable, expected) in cases {
+            let result = route_crtcs(&routes, |handle| {
+                unavailable.iter().any(|id| crtc(*id) == handle)
+            });
+
+            match (result, expected) {
+                (Ok(crtcs), Ok(expected)) => {
+                    let expected: Vec<_> = expected.into_iter().map(crtc).collect();
+                    assert_eq!(crtcs, expected, "{name}");
+                }
+                (Err(Error::NoFreeCrtc(display)), Err(id)) => {
+                    assert_eq!(display, DisplayId(Interface::DisplayPort, id), "{name}");
+                }
+                (result, expected) => panic!("{name}: got {result:?}, expected {expected:?}"),
+            }
+        }
+    }
+}
@@ -0,0 +1,183 @@
+use std::io::{self, Read, Write};
+
+use serde::{de::DeserializeOwned, Serialize};
+use thiserror::Error;
+
+/// The largest message payload either side accepts.
+pub const MAX_FRAME_SIZE: usize = 64 * 1024;
+
+/// Every frame starts with the payload length as a little-endian `u32`.
+pub const HEADER_SIZE: usize = std::mem::size_of::<u32>();
+
+#[derive(Error, Debug)]
+pub enum FrameError {
+    #[error("The frame of {0} bytes exceeds the limit of {MAX_FRAME_SIZE} bytes")]
+    TooLarge(usize),
+
+    #[error("Serialization error: {0}")]
+    Serialization(#[from] bincode::Error),
+
+    #[error("IO error: {0}")]
+    Io(#[from] io::Error),
+}
+
+/// Serializes a message into a complete frame, header included.
+pub fn encode<T: Serialize>(message: &T) -> Result<Vec<u8>, FrameError> {
+    let payload_size = bincode::serialized_size(message)? as usize;
+    if payload_size > MAX_FRAME_SIZE {
+        return Err(FrameError::TooLarge(payload_size));
+    }
+
+    let mut frame = Vec::with_capacity(HEADER_SIZE + payload_size);
+    frame.extend_from_slice(&(payload_size as u32).to_le_bytes());
+    bincode::serialize_into(&mut frame, message)?;
+
+    Ok(frame)
+}
+
+pub fn write_frame<W: Write, T: Serialize>(writer: &mut W, message: &T) -> Result<(), FrameError> {
+    writer.write_all(&encode(message)?)?;
+
+    Ok(())
+}
+
+pub fn read_frame<R: Read, T: DeserializeOwned>(reader: &mut R) -> Result<T, FrameError> {
+    let mut header = [0; HEADER_SIZE];
+    reader.read_exact(&mut header)?;
+
+    let payload_size = u32::from_le_bytes(header) as usize;
+    if payload_size > MAX_FRAME_SIZE {
+        return Err(FrameError::TooLarge(payload_size));
+    }
+
+    let mut payload = vec![0; payload_size];
+    reader.read_exact(&mut payload)?;
+
+    Ok(bincode::deserialize(&payload)?)
+}
+
+/// Splits a byte stream into frames when the bytes arrive in arbitrary chunks,
+/// e.g. from non-blocking reads or `recvmsg`.
+#[derive(Default)]
+pub struct FrameDecoder {
+    buffer: Vec<u8>,
+}
+
+impl FrameDecoder {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn push(&mut self, bytes: &[u8]) {
+        self.buffer.extend_from_slice(bytes);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.buffer.is_empty()
+    }
+
+    /// Whether a whole frame of the largest size fits in the buffered bytes,
+    /// so the next message can always be taken or rejected.
+    pub fn is_full(&self) -> bool {
+        self.buffer.len() >= HEADER_SIZE + MAX_FRAME_SIZE
+    }
+
+    /// Takes the next complete message out of the buffered bytes.
+    ///
+    /// Returns `Ok(None)` until a whole frame is buffered.
+    pub fn next_message<T: DeserializeOwned>(&mut self) -> Result<Option<T>, FrameError> {
+        let Some(header) = self.buffer.get(..HEADER_SIZE) else {
+            return Ok(None);
+        };
+
+        let payload_size = u32::from_le_bytes(header.try_into().unwrap()) as usize;
+        if payload_size > MAX_FRAME_SIZE {
+            return Err(FrameError::TooLarge(payload_size));
+        }
+
+        let frame_size = HEADER_SIZE + payload_size;
+        if self.buffer.len() < frame_size {
+            return Ok(None);
+        }
+
+        let message = bincode::deserialize(&self.buffer[HEADER_SIZE..frame_size]);
+        self.buffer.drain(..frame_size);
+
+        Ok(Some(message?))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Cursor;
+
+    use super::*;
+
+    #[test]
+    fn frame_round_trips() {
+        let frame = encode(&"hello").unwrap();
+        assert_eq!(
+            frame[..HEADER_SIZE],
+            ((frame.len() - HEADER_SIZE) as u32).to_le_bytes()
+        );
+
+        let mut reader = Cursor::new(frame);
+        assert_eq!(read_frame::<_, String>(&mut reader).unwrap(), "hello");
+    }
+
+    #[test]
+    fn decoder_waits_for_a_split_header() {
+        let frame = encode(&"split").unwrap();
+        let mut decoder = FrameDecoder::new();
+
+        decoder.push(&frame[..HEADER_SIZE - 1]);
+        assert!(decoder.next_message::<String>().unwrap().is_none());
+
+        decoder.push(&frame[HEADER_SIZE - 1..HEADER_SIZE + 1]);
+        assert!(decoder.next_message::<String>().unwrap().is_none());
+
+        decoder.push(&frame[HEADER_SIZE + 1..]);
+        assert_eq!(decoder.next_message::<String>().unwrap().unwrap(), "split");
+        assert!(decoder.is_empty());
+    }
+
+    #[test]
+    fn decoder_splits_several_frames_in_one_push() {
+        let mut bytes = encode(&"first").unwrap();
+        bytes.extend(encode(&"second").unwrap());
+        let third = encode(&"third").unwrap();
+        bytes.extend_from_slice(&third[..HEADER_SIZE + 1]);
+
+        let mut decoder = FrameDecoder::new();
+        decoder.push(&bytes);
+
+        assert_eq!(decoder.next_message::<String>().unwrap().unwrap(), "first");
+        assert_eq!(decoder.next_message::<String>().unwrap().unwrap(), "second");
+        assert!(decoder.next_message::<String>().unwrap().is_none());
+
+        decoder.push(&third[HEADER_SIZE + 1..]);
+        assert_eq!(decoder.next_message::<String>().unwrap().unwrap(), "third");
+        assert!(decoder.is_empty());
+    }
+
+    #[test]
+    fn oversized_frames_are_rejected() {
+        let too_large = vec![0u8; MAX_FRAME_SIZE];
+        assert!(matches!(encode(&too_large), Err(FrameError::TooLarge(_))));
+
+        let header = (MAX_FRAME_SIZE as u32 + 1).to_le_bytes();
+
+        let mut reader = Cursor::new(header);
+        assert!(matches!(
+            read_frame::<_, Vec<u8>>(&mut reader),
+            Err(FrameError::TooLarge(size)) if size == MAX_FRAME_SIZE + 1
+        ));
+
+        let mut decoder = FrameDecoder::new();
+        decoder.push(&header);
+        assert!(matches!(
+            decoder.next_message::<Vec<u8>>(),
+            Err(FrameError::TooLarge(size)) if size == MAX_FRAME_SIZE + 1
+        ));
+    }
+}
@@ -1,17 +1,405 @@
+use std::{fmt, ops::BitOr, path::PathBuf};
+
 use serde::{Deserialize, Serialize};
 
-#[derive(Serialize, Deserialize)]
+mod capi;
+pub mod client;
+pub mod framing;
+
+/// The protocol version described by the messages below.
+///
+/// The messages aren't compatible across versions, bincode can't skip what it doesn't know,
+/// so both sides must speak exactly this version. Bump it with every change to the messages.
+pub const PROTOCOL_VERSION: u32 = 2;
+
+/// The reply refusing a client of another protocol version than [`PROTOCOL_VERSION`].
+///
+/// `Hello` and `UnsupportedVersion` never change, so such a client is still told why.
+pub fn version_refusal(client_version: u32) -> Option<ServerMessage> {
+    (client_version != PROTOCOL_VERSION).then_some(ServerMessage::UnsupportedVersion {
+        min_version: PROTOCOL_VERSION,
+        max_version: PROTOCOL_VERSION,
+    })
+}
+
+/// Optional protocol features a peer implements.
+///
+/// Only the features announced by both sides in the `Hello` messages can be used.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub struct Capabilities(u64);
+
+impl Capabilities {
+    pub const NONE: Self = Self(0);
+
+    /// The client accepts the messages pushed by the server:
+    /// `LeaseRevoked`, `DisplayAdded` and `DisplayRemoved`.
+    pub const EVENTS: Self = Self(1 << 0);
+
+    /// The client follows its lease through `LeaseSuspended` and `LeaseResumed`,
+    /// otherwise the lease gets revoked instead of suspended.
+    pub const SUSPEND: Self = Self(1 << 1);
+
+    /// The client accepts `PrepareForSleep` and `ResumedFromSleep`.
+    pub const SLEEP: Self = Self(1 << 2);
+
+    /// The features implemented by this version of the protocol.
+    pub const SUPPORTED: Self = Self(Self::EVENTS.0 | Self::SUSPEND.0 | Self::SLEEP.0);
+
+    pub fn bits(self) -> u64 {
+        self.0
+    }
+
+    pub fn from_bits_truncate(bits: u64) -> Self {
+        Self(bits & Self::SUPPORTED.0)
+    }
+
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn intersection(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+}
+
+impl BitOr for Capabilities {
+    type Output = Self;
+
+    fn bitor(self, rhs: Self) -> Self::Output {
+        Self(self.0 | rhs.0)
+    }
+}
+
+/// A display a client asks to lease.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Hash, Debug)]
+pub enum DisplayId {
+    /// The kernel connector name, e.g. `HDMI-A-1`.
+    ///
+    /// The name can be prefixed with the card name, e.g. `card1-HDMI-A-1`,
+    /// to tell apart the same connector names of several GPUs.
+    Connector(String),
+
+    /// The identity of the monitor plugged into the connector.
+    Edid(EdidId),
+}
+
+impl fmt::Display for DisplayId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Connector(name) => write!(f, "{name}"),
+            Self::Edid(edid_id) => write!(f, "{edid_id}"),
+        }
+    }
+}
+
+/// The vendor and product identification block of an EDID.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Hash, Debug)]
+pub struct EdidId {
+    /// The three-letter PNP ID of the manufacturer, e.g. `VLV`.
+    pub manufacturer: String,
+    pub product_code: u16,
+    pub serial_number: u32,
+}
+
+impl EdidId {
+    const HEADER: [u8; 8] = [0x00, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0x00];
+
+    pub fn from_edid(edid: &[u8]) -> Option<Self> {
+        if edid.len() < 16 || edid[..8] != Self::HEADER {
+            return None;
+        }
+
+        let packed_manufacturer = u16::from_be_bytes([edid[8], edid[9]]);
+        let manufacturer = [10, 5, 0]
+            .into_iter()
+            .map(|shift| {
+                // 1 is `A`, anything out of `A..=Z` comes from a bogus or blank EDID.
+                let letter = ((packed_manufacturer >> shift) & 0x1f) as u8;
+                letter
+                    .checked_sub(1)
+                    .and_then(|letter| b'A'.checked_add(letter))
+                    .filter(u8::is_ascii_uppercase)
+                    .map_or('?', char::from)
+            })
+            .collect();
+
+        Some(Self {
+            manufacturer,
+            product_code: u16::from_le_bytes([edid[10], edid[11]]),
+            serial_number: u32::from_le_bytes([edid[12], edid[13], edid[14], edid[15]]),
+        })
+    }
+}
+
+impl fmt::Display for EdidId {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}-{:04x}-{}",
+            self.manufacturer, self.product_code, self.serial_number
+        )
+    }
+}
+
+/// The GPU a lease fd was created on.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct CardInfo {
+    /// E.g. `/dev/dri/card0`.
+    pub devnode: PathBuf,
+    /// E.g. `card0`.
+    pub sysname: String,
+    /// The kernel driver of the GPU, e.g. `amdgpu`.
+    pub driver: Option<String>,
+    /// Only PCI GPUs have one.
+    pub pci: Option<PciInfo>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct PciInfo {
+    /// E.g. `0000:03:00.0`.
+    pub slot: String,
+    pub vendor_id: u16,
+    pub device_id: u16,
+    pub subsystem_vendor_id: Option<u16>,
+    pub subsystem_device_id: Option<u16>,
+}
+
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct LeasedConnector {
+    /// The DRM object id of the connector.
+    pub handle: u32,
+    /// The kernel connector name, e.g. `HDMI-A-1`.
+    pub name: String,
+}
+
+/// Describes one lease fd passed along with `ServerMessage::LeaseGranted`.
+#[derive(Serialize, Deserialize, Clone, PartialEq, Eq, Debug)]
+pub struct LeaseGrant {
+    pub card: CardInfo,
+    pub lessee_id: u32,
+    pub connectors: Vec<LeasedConnector>,
+    /// The DRM object ids of the leased CRTCs.
+    pub crtcs: Vec<u32>,
+    /// The DRM object ids of the leased planes.
+    pub planes: Vec<u32>,
+}
+
+/// What to do when some of the requested cards can't be leased.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum LeaseMode {
+    /// Fail the whole request, no display gets leased.
+    #[default]
+    AllOrNothing,
+
+    /// Lease the displays of the cards that succeeded and skip the rest.
+    BestEffort,
+}
+
+/// The kind of failure reported by `ServerMessage::Error`.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum ErrorCode {
+    /// The kernel refused a DRM request.
+    Drm,
+
+    /// The client isn't allowed to do what it asked for.
+    Permission,
+
+    /// The GPU ran out of the resources the request needs, e.g. CRTCs or planes.
+    Resource,
+
+    /// The client broke the protocol.
+    Protocol,
+
+    /// Something went wrong on the server side.
+    Internal,
+}
+
+/// Why the server took a lease back.
+#[derive(Serialize, Deserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum RevokeReason {
+    /// The client asked for it with `ReleaseDisplays`.
+    Released,
+
+    /// The GPU of the lease is gone.
+    DeviceRemoved,
+
+    /// logind has removed the seat of the lease holder.
+    SeatRemoved,
+
+    /// The logind session of the lease holder has ended.
+    SessionEnded,
+
+    /// Another session became the active one on the seat.
+    SessionInactive,
+
+    /// A display of the lease is gone, e.g. unplugged while the system was asleep.
+    DisplayRemoved,
+
+    /// The server is stopping.
+    Shutdown,
+
+    /// The process the lease was granted to has exited,
+    /// e.g. after passing the connection on to a child.
+    HolderExited,
+}
+
+/// The connection stays open after a request.
+///
+/// Closing the connection releases the leases granted over it.
+#[derive(Serialize, Deserialize, Clone)]
 pub enum ServerMessage {
-    LeaseGranted,
-    LeaseRevoked,
+    Hello {
+        protocol_version: u32,
+        capabilities: Capabilities,
+    },
+    UnsupportedVersion {
+        min_version: u32,
+        max_version: u32,
+    },
+    /// Carries one lease fd per card, in the same order as `leases`.
+    LeaseGranted {
+        leases: Vec<LeaseGrant>,
+    },
+    LeaseRevoked {
+        reason: RevokeReason,
+    },
     LeaseNotFound,
     NoPermission,
     SeatBusy,
     NoDisplays,
+    UnknownDisplay(DisplayId),
+    DisplayUnavailable(DisplayId),
+    AmbiguousDisplay(DisplayId),
+    DisplayBusy(DisplayId),
+    /// An `AllOrNothing` request failed on the card, nothing got leased.
+    LeaseFailed {
+        card: CardInfo,
+        message: String,
+    },
+    /// Any failure without a dedicated message.
+    Error {
+        code: ErrorCode,
+        message: String,
+    },
+    /// A display showed up on the client's seat.
+    ///
+    /// Always a `DisplayId::Connector` prefixed with the card name.
+    DisplayAdded(DisplayId),
+    /// A display of the client's seat is gone.
+    DisplayRemoved(DisplayId),
+    /// The lease fds stopped working as the client's session is inactive,
+    /// the displays stay reserved for the client.
+    LeaseSuspended,
+    /// The session is active again, carries the new lease fds like `LeaseGranted`.
+    LeaseResumed {
+        leases: Vec<LeaseGrant>,
+    },
+    /// The system is about to sleep, the lessees have a moment to release or save their state.
+    PrepareForSleep,
+    /// The system has resumed, the leases of the displays gone meanwhile are revoked.
+    ResumedFromSleep,
 }
 
+/// A client starts every connection with `Hello` and waits for the server's `Hello`
+/// before sending any request.
 #[derive(Serialize, Deserialize)]
 pub enum ClientMessage {
-    RequestDisplays,
+    Hello {
+        protocol_version: u32,
+        client_name: String,
+        capabilities: Capabilities,
+    },
+    /// Asks to lease the given displays of the client's seat.
+    ///
+    /// An empty list asks for every display of the seat.
+    RequestDisplays {
+        displays: Vec<DisplayId>,
+        mode: LeaseMode,
+    },
     ReleaseDisplays,
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An EDID header followed by the packed manufacturer letters, product code and serial.
+    fn edid(letters: [u16; 3]) -> Vec<u8> {
+        let packed = (letters[0] << 10) | (letters[1] << 5) | letters[2];
+
+        let mut edid = EdidId::HEADER.to_vec();
+        edid.extend(packed.to_be_bytes());
+        edid.extend(0x1234u16.to_le_bytes());
+        edid.extend(42u32.to_le_bytes());
+        edid
+    }
+
+    #[test]
+    fn decodes_manufacturer_letters() {
+        let edid_id = EdidId::from_edid(&edid([22, 12, 22])).unwrap();
+
+        assert_eq!(edid_id.manufacturer, "VLV");
+        assert_eq!(edid_id.product_code, 0x1234);
+        assert_eq!(edid_id.serial_number, 42);
+    }
+
+    #[test]
+    fn replaces_invalid_manufacturer_letters() {
+        let cases = [
+            ([0, 1, 26], "?AZ"),
+            ([27, 1, 1], "?AA"),
+            ([1, 31, 1], "A?A"),
+            ([0, 0, 0], "???"),
+        ];
+
+        for (letters, manufacturer) in cases {
+            let edid_id = EdidId::from_edid(&edid(letters)).unwrap();
+            assert_eq!(edid_id.manufacturer, manufacturer, "{letters:?}");
+        }
+    }
+
+    #[test]
+    fn only_the_same_protocol_version_is_accepted() {
+        assert!(version_refusal(PROTOCOL_VERSION).is_none());
+
+        // E.g. the v1 clients, released before the sleep and suspend messages.
+        for client_version in [1, PROTOCOL_VERSION + 1] {
+            assert!(
+                matches!(
+                    version_refusal(client_version),
+                    Some(ServerMessage::UnsupportedVersion {
+                        min_version: PROTOCOL_VERSION,
+                        max_version: PROTOCOL_VERSION,
+                    })
+                ),
+                "{client_version}",
+            );
+        }
+    }
+
+    #[test]
+    fn v1_hello_is_refused() {
+        let v1_hello = ClientMessage::Hello {
+            protocol_version: 1,
+            client_name: "v1".to_string(),
+            capabilities: Capabilities::EVENTS,
+        };
+
+        let frame = framing::encode(&v1_hello).unwrap();
+        let hello: ClientMessage = framing::read_frame(&mut frame.as_slice()).unwrap();
+        let ClientMessage::Hello {
+            protocol_version, ..
+        } = hello
+        else {
+            panic!("Expected Hello");
+        };
+
+        assert!(version_refusal(protocol_version).is_some());
+    }
+
+    #[test]
+    fn rejects_truncated_or_headerless_edid() {
+        assert_eq!(EdidId::from_edid(&edid([1, 1, 1])[..15]), None);
+        assert_eq!(EdidId::from_edid(&[0; 16]), None);
+    }
+}
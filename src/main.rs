@@ -1,39 +1,91 @@
 use clap::Parser;
+use display_distributor::ErrorCode;
 use log::{error, info};
 use thiserror::Error;
 
-use crate::distributor::Distributor;
+use crate::{
+    distributor::{parse_seat_policy, Distributor, InactivePolicy, SeatId, SessionPolicy},
+    drm::PlanePolicy,
+};
 
+mod connection;
 mod dbus;
 mod distributor;
 mod drm;
 mod logging;
+mod peer;
+mod pidfd;
 
 #[derive(Error, Debug)]
 pub enum Error {
     #[error("DBus connection lost")]
     DBusLost,
 
-    #[error("Unable to parse a drm's connector `<interface>-<id>`")]
-    UnableToParseDisplayId,
+    #[error("Unable to parse the drm connector name `{0}`, expected `<interface>-<id>`")]
+    UnableToParseDisplayId(String),
 
     #[error("The current session is not bind to a seat")]
     NoSeat,
 
+    #[error("The session is not the active one on its seat")]
+    InactiveSession,
+
+    #[error("Remote sessions can't lease displays")]
+    RemoteSession,
+
+    #[error("Sessions of the class `{0}` can't lease displays")]
+    SessionClass(String),
+
     #[error("Seat has no displays")]
     NoDisplays,
 
+    #[error("No free CRTC left for the display {0}")]
+    NoFreeCrtc(drm::DisplayId),
+
+    #[error("No free primary plane for the CRTC {0}")]
+    NoPrimaryPlane(u32),
+
+    #[error("Seat displays are already leased")]
+    SeatBusy,
+
+    #[error("The display {0} is not found")]
+    UnknownDisplay(display_distributor::DisplayId),
+
+    #[error("The display {0} doesn't belong to the seat")]
+    DisplayUnavailable(display_distributor::DisplayId),
+
+    #[error("Several displays of the seat match {0}")]
+    AmbiguousDisplay(display_distributor::DisplayId),
+
+    #[error("The display {0} is already leased")]
+    DisplayBusy(display_distributor::DisplayId),
+
+    #[error("Unable to lease the displays of {}: {source}", .card.devnode.display())]
+    LeaseFailed {
+        card: Box<display_distributor::CardInfo>,
+        source: Box<Error>,
+    },
+
     #[error("Unable to discover a peer PID")]
     NoPeerPid,
 
+    #[error("The peer process has exited")]
+    PeerGone,
+
     #[error("Invalid message from a peer")]
     PeerBadMsg,
 
+    #[error("The peer didn't start with a handshake")]
+    NoHandshake,
+
+    #[error("The peer speaks an unsupported protocol version {0}")]
+    UnsupportedProtocolVersion(u32),
+
     #[error("DRM error: {0}")]
     Drm(#[from] ::drm::SystemError),
 
-    #[error("Serialization error: {0}")]
-    Serialization(#[from] bincode::Error),
+    #[error("Framing error: {0}")]
+    Frame(#[from] display_distributor::framing::FrameError),
 
     #[error("IO error: {0}")]
     Io(#[from] std::io::Error),
@@ -43,28 +95,206 @@ pub enum Error {
 
     #[error("DBus error: {0}")]
     DBus(#[from] ::dbus::Error),
+
+    #[cfg(feature = "wayland")]
+    #[error("Wayland error: {0}")]
+    WaylandInit(#[from] wayland_server::backend::InitError),
+
+    #[cfg(feature = "wayland")]
+    #[error("Unable to bind the Wayland socket: {0}")]
+    WaylandBind(#[from] wayland_server::BindError),
+}
+
+impl Error {
+    /// How the error is reported to clients.
+    pub fn code(&self) -> ErrorCode {
+        use Error::*;
+
+        match self {
+            Drm(_) => ErrorCode::Drm,
+            NoSeat
+            | InactiveSession
+            | RemoteSession
+            | SessionClass(_)
+            | DisplayUnavailable(_)
+            | NoPeerPid => ErrorCode::Permission,
+            NoDisplays | NoFreeCrtc(_) | NoPrimaryPlane(_) | SeatBusy | UnknownDisplay(_)
+            | AmbiguousDisplay(_) | DisplayBusy(_) => ErrorCode::Resource,
+            LeaseFailed { source, .. } => source.code(),
+            PeerBadMsg | NoHandshake | UnsupportedProtocolVersion(_) | Frame(_) => {
+                ErrorCode::Protocol
+            }
+            DBusLost | UnableToParseDisplayId(_) | PeerGone | Io(_) | Env(_) | DBus(_) => {
+                ErrorCode::Internal
+            }
+            #[cfg(feature = "wayland")]
+            WaylandInit(_) | WaylandBind(_) => ErrorCode::Internal,
+        }
+    }
 }
 
 #[derive(Parser)]
 struct Cli {
     #[arg(short, long, default_value_t = log::LevelFilter::Info)]
     log_level: log::LevelFilter,
+
+    /// Which planes of the leased CRTCs go into a lease
+    #[arg(long, value_enum, default_value_t = PlanePolicy::default())]
+    plane_policy: PlanePolicy,
+
+    /// What happens to the leases of a session once another session on the seat becomes active
+    #[arg(long, value_enum, default_value_t = InactivePolicy::default())]
+    inactive_policy: InactivePolicy,
+
+    /// Overrides --inactive-policy for a seat, given as `<seat>=<policy>`; can be repeated
+    #[arg(long, value_parser = parse_seat_policy)]
+    seat_inactive_policy: Vec<(SeatId, InactivePolicy)>,
+
+    /// Distribute the displays of every logind seat instead of the daemon's own seat
+    #[arg(long)]
+    multi_seat: bool,
+
+    /// Also serve the wp_drm_lease_device_v1 Wayland protocol on the socket,
+    /// a name in XDG_RUNTIME_DIR or an absolute path
+    #[cfg(feature = "wayland")]
+    #[arg(long)]
+    wayland_socket: Option<std::path::PathBuf>,
 }
 
 fn main() {
     let cli = Cli::parse();
     logging::setup(cli.log_level).expect("Couldn't setup logging");
 
-    if let Err(err) = run() {
+    if let Err(err) = run(cli) {
         error!("{err}");
     }
 }
 
-fn run() -> Result<(), Error> {
+fn run(cli: Cli) -> Result<(), Error> {
     info!("The {} is started", env!("CARGO_PKG_NAME"));
 
-    let mut distributor = Distributor::new()?;
+    let session_policy = SessionPolicy::new(cli.inactive_policy, cli.seat_inactive_policy);
+    let mut distributor = Distributor::new(cli.plane_policy, session_policy, cli.multi_seat)?;
+
+    #[cfg(feature = "wayland")]
+    if let Some(socket) = cli.wayland_socket {
+        distributor.serve_wayland(socket)?;
+    }
+
     distributor.listen_clients()?;
 
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use ::drm::control::connector::Interface;
+    use display_distributor::{framing::FrameError, CardInfo};
+
+    use super::*;
+
+    fn display() -> display_distributor::DisplayId {
+        display_distributor::DisplayId::Connector("HDMI-A-1".to_string())
+    }
+
+    fn lease_failed(source: Error) -> Error {
+        Error::LeaseFailed {
+            card: Box::new(CardInfo {
+                devnode: "/dev/dri/card0".into(),
+                sysname: "card0".to_string(),
+                driver: None,
+                pci: None,
+            }),
+            source: Box::new(source),
+        }
+    }
+
+    #[test]
+    fn errors_have_codes() {
+        let cases = [
+            (
+                Error::Drm(::drm::SystemError::PermissionDenied),
+                ErrorCode::Drm,
+            ),
+            (Error::NoSeat, ErrorCode::Permission),
+            (Error::InactiveSession, ErrorCode::Permission),
+            (Error::RemoteSession, ErrorCode::Permission),
+            (
+                Error::SessionClass("background".to_string()),
+                ErrorCode::Permission,
+            ),
+            (Error::DisplayUnavailable(display()), ErrorCode::Permission),
+            (Error::NoPeerPid, ErrorCode::Permission),
+            (Error::NoDisplays, ErrorCode::Resource),
+            (
+                Error::NoFreeCrtc(drm::DisplayId(Interface::HDMIA, 1)),
+                ErrorCode::Resource,
+            ),
+            (Error::NoPrimaryPlane(42), ErrorCode::Resource),
+            (Error::SeatBusy, ErrorCode::Resource),
+            (Error::UnknownDisplay(display()), ErrorCode::Resource),
+            (Error::AmbiguousDisplay(display()), ErrorCode::Resource),
+            (Error::DisplayBusy(display()), ErrorCode::Resource),
+            (Error::PeerBadMsg, ErrorCode::Protocol),
+            (Error::NoHandshake, ErrorCode::Protocol),
+            (Error::UnsupportedProtocolVersion(1), ErrorCode::Protocol),
+            (
+                Error::Frame(FrameError::TooLarge(usize::MAX)),
+                ErrorCode::Protocol,
+            ),
+            (Error::DBusLost, ErrorCode::Internal),
+            (
+                Error::UnableToParseDisplayId("HDMI".to_string()),
+                ErrorCode::Internal,
+            ),
+            (Error::PeerGone, ErrorCode::Internal),
+            (
+                Error::Io(std::io::ErrorKind::Other.into()),
+                ErrorCode::Internal,
+            ),
+            (
+                Error::Env(std::env::VarError::NotPresent),
+                ErrorCode::Internal,
+            ),
+            (
+                Error::DBus(::dbus::Error::new_failed("failed")),
+                ErrorCode::Internal,
+            ),
+        ];
+
+        for (err, code) in cases {
+            assert_eq!(err.code(), code, "{err:?}");
+        }
+    }
+
+    #[cfg(feature = "wayland")]
+    #[test]
+    fn wayland_errors_are_internal() {
+        use wayland_server::{backend::InitError, BindError};
+
+        assert_eq!(
+            Error::WaylandInit(InitError::NoWaylandLib).code(),
+            ErrorCode::Internal
+        );
+        assert_eq!(
+            Error::WaylandBind(BindError::AlreadyInUse).code(),
+            ErrorCode::Internal
+        );
+    }
+
+    #[test]
+    fn failed_leases_have_the_code_of_the_failure() {
+        let cases = [
+            (
+                Error::Drm(::drm::SystemError::PermissionDenied),
+                ErrorCode::Drm,
+            ),
+            (Error::NoPrimaryPlane(42), ErrorCode::Resource),
+            (Error::DisplayUnavailable(display()), ErrorCode::Permission),
+        ];
+
+        for (source, code) in cases {
+            assert_eq!(lease_failed(source).code(), code);
+        }
+    }
+}
@@ -0,0 +1,148 @@
+use std::{
+    io, mem,
+    os::{
+        fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd},
+        unix::net::UnixStream,
+    },
+};
+
+use libc::pid_t;
+use nix::poll::{poll, PollFd, PollFlags};
+
+use crate::{pidfd::PidFd, Error};
+
+/// Not yet exported by the `libc` crate, available since Linux 6.5.
+const SO_PEERPIDFD: libc::c_int = 77;
+
+/// The identity of a process on the other end of a client connection.
+///
+/// The PID alone is not enough to identify a client: once the process exits,
+/// the PID can be reused by an unrelated process. The pidfd pins the exact process,
+/// so as long as the pidfd is alive, the PID refers to the same process.
+pub struct Peer {
+    pid: pid_t,
+    pidfd: PidFd,
+}
+
+impl Peer {
+    pub fn from_stream(stream: &UnixStream) -> Result<Self, Error> {
+        let (Some(pid), ..) = unix_cred::get_peer_pid_ids(stream)? else {
+            return Err(Error::NoPeerPid);
+        };
+
+        let pidfd = match peer_pidfd(stream) {
+            Ok(pidfd) => pidfd,
+            Err(err) if err.raw_os_error() == Some(libc::ENOPROTOOPT) => {
+                let pidfd = PidFd::open(pid)?;
+                check_opened_pidfd(stream, &pidfd)?;
+
+                pidfd
+            }
+            Err(err) => return Err(err.into()),
+        };
+
+        let peer = Self { pid, pidfd };
+        if !peer.is_alive() {
+            return Err(Error::PeerGone);
+        }
+
+        Ok(peer)
+    }
+
+    /// Pins a process known by its PID, for the tests that have no client connection.
+    #[cfg(test)]
+    pub fn from_pid(pid: pid_t) -> Result<Self, Error> {
+        Ok(Self {
+            pid,
+            pidfd: PidFd::open(pid)?,
+        })
+    }
+
+    pub fn try_clone(&self) -> Result<Self, Error> {
+        Ok(Self {
+            pid: self.pid,
+            pidfd: self.pidfd.try_clone()?,
+        })
+    }
+
+    pub fn pid(&self) -> pid_t {
+        self.pid
+    }
+
+    pub fn is_alive(&self) -> bool {
+        self.pidfd.is_alive()
+    }
+
+    /// Whether both identities refer to one process.
+    ///
+    /// A PID can't be reused while a process is alive,
+    /// so two living peers with the same PID are the same process.
+    pub fn is_same_process(&self, other: &Peer) -> bool {
+        self.pid == other.pid && self.is_alive() && other.is_alive()
+    }
+}
+
+impl AsFd for Peer {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.pidfd.as_fd()
+    }
+}
+
+fn peer_pidfd(stream: &UnixStream) -> io::Result<PidFd> {
+    let mut fd: libc::c_int = -1;
+    let mut len = mem::size_of::<libc::c_int>() as libc::socklen_t;
+
+    let result = unsafe {
+        libc::getsockopt(
+            stream.as_raw_fd(),
+            libc::SOL_SOCKET,
+            SO_PEERPIDFD,
+            &mut fd as *mut libc::c_int as *mut libc::c_void,
+            &mut len,
+        )
+    };
+
+    if result < 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    // SAFETY: `SO_PEERPIDFD` installs a new pidfd that is owned by nobody else.
+    let fd = unsafe { OwnedFd::from_raw_fd(fd) };
+
+    Ok(PidFd::from_owned_fd(fd))
+}
+
+/// Checks that a pidfd opened from the peer PID, without `SO_PEERPIDFD`, refers to the peer.
+///
+/// The peer could have exited before the pidfd got opened,
+/// so the PID could already belong to another process.
+/// The peer credentials are read again and must name the process the pidfd refers to,
+/// and the connection must still be held by somebody.
+///
+/// This leaves a race: if the peer exits and its PID gets reused before the pidfd is opened,
+/// while a process that inherited the connection fd still holds it,
+/// the pidfd refers to an unrelated process. Only `SO_PEERPIDFD` rules it out.
+fn check_opened_pidfd(stream: &UnixStream, pidfd: &PidFd) -> Result<(), Error> {
+    let (Some(peer_pid), ..) = unix_cred::get_peer_pid_ids(stream)? else {
+        return Err(Error::NoPeerPid);
+    };
+
+    if pidfd.pid()? != Some(peer_pid) || is_hung_up(stream) {
+        return Err(Error::PeerGone);
+    }
+
+    Ok(())
+}
+
+fn is_hung_up(stream: &UnixStream) -> bool {
+    let mut poll_fds = [PollFd::new(stream.as_raw_fd(), PollFlags::empty())];
+
+    match poll(&mut poll_fds, 0) {
+        Ok(0) => false,
+        Ok(_) => poll_fds[0]
+            .revents()
+            .map(|revents| revents.contains(PollFlags::POLLHUP))
+            .unwrap_or(true),
+        Err(_) => true,
+    }
+}
@@ -0,0 +1,85 @@
+use std::{
+    fs, io,
+    os::fd::{AsFd, AsRawFd, BorrowedFd, FromRawFd, OwnedFd, RawFd},
+};
+
+use libc::pid_t;
+use nix::poll::{poll, PollFd, PollFlags};
+
+/// A process handle that stays bound to the process it was opened for.
+///
+/// The fd becomes readable as soon as the process exits,
+/// so it can be polled together with the other daemon fds.
+pub struct PidFd {
+    fd: OwnedFd,
+}
+
+impl PidFd {
+    pub fn open(pid: pid_t) -> io::Result<Self> {
+        let fd = unsafe { libc::syscall(libc::SYS_pidfd_open, pid, 0) };
+        if fd < 0 {
+            return Err(io::Error::last_os_error());
+        }
+
+        // SAFETY: `pidfd_open` returned a new fd that is owned by nobody else.
+        let fd = unsafe { OwnedFd::from_raw_fd(fd as RawFd) };
+
+        Ok(Self { fd })
+    }
+
+    /// Wraps an fd that is known to be a pidfd, e.g. the one returned by `SO_PEERPIDFD`.
+    pub fn from_owned_fd(fd: OwnedFd) -> Self {
+        Self { fd }
+    }
+
+    pub fn try_clone(&self) -> io::Result<Self> {
+        Ok(Self {
+            fd: self.fd.try_clone()?,
+        })
+    }
+
+    /// The PID of the process as the kernel reports it for the pidfd, `None` once it has exited.
+    pub fn pid(&self) -> io::Result<Option<pid_t>> {
+        let fdinfo = fs::read_to_string(format!["/proc/self/fdinfo/{}", self.fd.as_raw_fd()])?;
+
+        let pid = fdinfo
+            .lines()
+            .find_map(|line| line.strip_prefix("Pid:"))
+            .and_then(|pid| pid.trim().parse::<pid_t>().ok())
+            .ok_or_else(|| {
+                io::Error::new(io::ErrorKind::InvalidData, "No PID in the pidfd info")
+            })?;
+
+        Ok((pid > 0).then_some(pid))
+    }
+
+    pub fn is_alive(&self) -> bool {
+        let mut poll_fds = [PollFd::new(self.fd.as_raw_fd(), PollFlags::POLLIN)];
+
+        match poll(&mut poll_fds, 0) {
+            Ok(0) => true,
+            Ok(_) => false,
+            Err(_) => true,
+        }
+    }
+}
+
+impl AsFd for PidFd {
+    fn as_fd(&self) -> BorrowedFd<'_> {
+        self.fd.as_fd()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reports_the_pid_it_was_opened_for() {
+        let pid = std::process::id() as pid_t;
+        let pidfd = PidFd::open(pid).unwrap();
+
+        assert_eq!(pidfd.pid().unwrap(), Some(pid));
+        assert!(pidfd.is_alive());
+    }
+}
@@ -0,0 +1,101 @@
+mod common;
+
+use std::{
+    env, fs,
+    io::Read,
+    os::unix::net::UnixStream,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use display_distributor::{
+    client::SOCKET_ENV, ClientMessage, DisplayId, RevokeReason, ServerMessage,
+};
+
+use common::{accept_hello, expect_request, grant, recv, send, send_with_fds, TestServer};
+
+/// Builds the C program against the header and the shared library of the crate.
+fn build_c_program(source: &Path) -> PathBuf {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    // The cdylib is built next to the test binary.
+    let library_dir = env::current_exe().unwrap().parent().unwrap().to_path_buf();
+    let output = Path::new(env!("CARGO_TARGET_TMPDIR")).join(source.file_stem().unwrap());
+
+    let status = Command::new(env::var_os("CC").unwrap_or_else(|| "cc".into()))
+        .arg(manifest_dir.join(source))
+        .arg("-Wall")
+        .arg("-Werror")
+        .arg("-I")
+        .arg(manifest_dir.join("include"))
+        .arg("-L")
+        .arg(&library_dir)
+        .arg(format!("-Wl,-rpath,{}", library_dir.display()))
+        .arg("-ldisplay_distributor")
+        .arg("-o")
+        .arg(&output)
+        .status()
+        .unwrap();
+    assert!(status.success(), "Unable to build {}", source.display());
+
+    output
+}
+
+#[test]
+fn header_matches_the_c_api() {
+    let manifest_dir = Path::new(env!("CARGO_MANIFEST_DIR"));
+    let config = cbindgen::Config::from_file(manifest_dir.join("cbindgen.toml")).unwrap();
+
+    let mut generated = Vec::new();
+    cbindgen::generate_with_config(manifest_dir, config)
+        .unwrap()
+        .write(&mut generated);
+
+    let header = fs::read(manifest_dir.join("include/display_distributor.h")).unwrap();
+    assert!(
+        generated == header,
+        "include/display_distributor.h is out of date, regenerate it with cbindgen",
+    );
+}
+
+#[test]
+fn c_program_leases_and_releases() {
+    let program = build_c_program(Path::new("tests/capi/lease.c"));
+
+    let server = TestServer::start(|mut stream| {
+        accept_hello(&mut stream);
+        assert_eq!(
+            expect_request(&mut stream),
+            [DisplayId::Connector("HDMI-A-1".to_string())],
+        );
+
+        let (card_fd, mut card_end) = UnixStream::pair().unwrap();
+        send_with_fds(
+            &mut stream,
+            ServerMessage::LeaseGranted {
+                leases: vec![grant("card0", 1, "HDMI-A-1")],
+            },
+            &[card_fd.into()],
+        );
+
+        assert!(matches!(recv(&mut stream), ClientMessage::ReleaseDisplays));
+        send(
+            &mut stream,
+            ServerMessage::LeaseRevoked {
+                reason: RevokeReason::Released,
+            },
+        );
+
+        let mut received = [0];
+        card_end.read_exact(&mut received).unwrap();
+        received[0]
+    });
+
+    let status = Command::new(program)
+        .arg(server.path())
+        .env_remove(SOCKET_ENV)
+        .status()
+        .unwrap();
+
+    assert!(status.success());
+    assert_eq!(server.join(), b'c');
+}
@@ -0,0 +1,52 @@
+/* Leases a display through the C API from the stand-in server at argv[1]. */
+
+#include <stdio.h>
+#include <stdlib.h>
+#include <unistd.h>
+
+#include "display_distributor.h"
+
+#define CHECK(condition)                                                       \
+    do {                                                                       \
+        if (!(condition)) {                                                    \
+            const char *error = dd_last_error();                              \
+            fprintf(stderr, "%s:%d: %s failed (%s)\n", __FILE__, __LINE__,      \
+                    #condition, error ? error : "no error");                   \
+            exit(1);                                                           \
+        }                                                                      \
+    } while (0)
+
+int main(int argc, char **argv) {
+    dd_client *client = NULL;
+    dd_lease *lease = NULL;
+    const char *connectors[] = {"HDMI-A-1"};
+    uint32_t handles[4];
+
+    CHECK(argc == 2);
+
+    /* The test runs without DISPLAY_DISTRIBUTOR_SOCKET. */
+    CHECK(dd_connect(NULL, &client) == DD_STATUS_NO_SOCKET_PATH);
+    CHECK(dd_last_error() != NULL);
+
+    CHECK(dd_lease_get_card_count(NULL) == 0);
+    CHECK(dd_lease_get_fd(NULL, 0) == -1);
+    CHECK(dd_lease_get_connectors(NULL, 0, handles, 4) == 0);
+
+    CHECK(dd_connect(argv[1], &client) == DD_STATUS_OK);
+    CHECK(dd_request_displays(client, connectors, 1, DD_LEASE_MODE_ALL_OR_NOTHING, &lease) ==
+          DD_STATUS_OK);
+
+    CHECK(dd_lease_get_card_count(lease) == 1);
+    CHECK(dd_lease_get_fd(lease, 1) == -1);
+    CHECK(dd_lease_get_connectors(lease, 0, handles, 4) == 1);
+    CHECK(handles[0] == 10);
+
+    /* The server keeps the other end of the lease fd and expects the byte. */
+    int fd = dd_lease_get_fd(lease, 0);
+    CHECK(fd >= 0);
+    CHECK(write(fd, "c", 1) == 1);
+
+    CHECK(dd_release(lease) == DD_STATUS_OK);
+
+    return 0;
+}
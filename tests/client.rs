@@ -0,0 +1,354 @@
+mod common;
+
+use std::{
+    env,
+    io::{Read, Write},
+    os::unix::net::UnixStream,
+};
+
+use display_distributor::{
+    client::{Client, ClientError, Event, SOCKET_ENV},
+    Capabilities, ClientMessage, DisplayId, LeaseMode, RevokeReason, ServerMessage,
+    PROTOCOL_VERSION,
+};
+
+use common::{accept_hello, expect_request, grant, recv, send, send_with_fds, TestServer};
+
+#[test]
+fn lease_carries_fds_with_their_grants() {
+    let server = TestServer::start(|mut stream| {
+        accept_hello(&mut stream);
+        assert_eq!(
+            expect_request(&mut stream),
+            [DisplayId::Connector("HDMI-A-1".to_string())],
+        );
+
+        // The client gets one end of each pair, so the fds can be told apart.
+        let (card0_fd, card0_end) = UnixStream::pair().unwrap();
+        let (card1_fd, card1_end) = UnixStream::pair().unwrap();
+
+        send_with_fds(
+            &mut stream,
+            ServerMessage::LeaseGranted {
+                leases: vec![grant("card0", 1, "HDMI-A-1"), grant("card1", 2, "DP-1")],
+            },
+            &[card0_fd.into(), card1_fd.into()],
+        );
+
+        (stream, card0_end, card1_end)
+    });
+
+    let lease = Client::connect(server.path())
+        .unwrap()
+        .request_displays(
+            &[DisplayId::Connector("HDMI-A-1".to_string())],
+            LeaseMode::AllOrNothing,
+        )
+        .unwrap();
+
+    let (_stream, mut card0_end, mut card1_end) = server.join();
+
+    let cards = lease.cards();
+    assert_eq!(cards.len(), 2);
+    assert_eq!(cards[0].grant, grant("card0", 1, "HDMI-A-1"));
+    assert_eq!(cards[1].grant, grant("card1", 2, "DP-1"));
+
+    for (card, end, byte) in [
+        (&cards[0], &mut card0_end, b'0'),
+        (&cards[1], &mut card1_end, b'1'),
+    ] {
+        let mut fd = UnixStream::from(card.fd.try_clone().unwrap());
+        fd.write_all(&[byte]).unwrap();
+
+        let mut received = [0];
+        end.read_exact(&mut received).unwrap();
+        assert_eq!(received[0], byte);
+    }
+}
+
+#[test]
+fn refusal_is_reported_as_error() {
+    let server = TestServer::start(|mut stream| {
+        accept_hello(&mut stream);
+        let displays = expect_request(&mut stream);
+        send(&mut stream, ServerMessage::DisplayBusy(displays[0].clone()));
+    });
+
+    let display = DisplayId::Connector("card0-DP-2".to_string());
+    let result = Client::connect(server.path())
+        .unwrap()
+        .request_displays(std::slice::from_ref(&display), LeaseMode::BestEffort);
+
+    server.join();
+    assert!(matches!(result, Err(ClientError::DisplayBusy(busy)) if busy == display));
+}
+
+#[test]
+fn unsupported_version_fails_to_connect() {
+    let server = TestServer::start(|mut stream| {
+        recv(&mut stream);
+        send(
+            &mut stream,
+            ServerMessage::UnsupportedVersion {
+                min_version: PROTOCOL_VERSION + 1,
+                max_version: PROTOCOL_VERSION + 1,
+            },
+        );
+    });
+
+    let result = Client::connect(server.path());
+
+    server.join();
+    assert!(matches!(
+        result,
+        Err(ClientError::UnsupportedVersion { min_version, .. }) if min_version == PROTOCOL_VERSION + 1
+    ));
+}
+
+#[test]
+fn server_of_another_version_is_refused() {
+    let server = TestServer::start(|mut stream| {
+        recv(&mut stream);
+        send(
+            &mut stream,
+            ServerMessage::Hello {
+                protocol_version: PROTOCOL_VERSION - 1,
+                capabilities: Capabilities::NONE,
+            },
+        );
+    });
+
+    let result = Client::connect(server.path());
+
+    server.join();
+    assert!(matches!(
+        result,
+        Err(ClientError::UnsupportedVersion { min_version, .. }) if min_version == PROTOCOL_VERSION - 1
+    ));
+}
+
+#[test]
+fn events_arriving_before_the_reply_are_kept() {
+    let added = DisplayId::Connector("card0-DP-3".to_string());
+    let removed = DisplayId::Connector("card0-DP-4".to_string());
+
+    let server = TestServer::start({
+        let added = added.clone();
+        let removed = removed.clone();
+
+        move |mut stream| {
+            accept_hello(&mut stream);
+            expect_request(&mut stream);
+
+            send(&mut stream, ServerMessage::DisplayAdded(added));
+
+            let (card_fd, _) = UnixStream::pair().unwrap();
+            send_with_fds(
+                &mut stream,
+                ServerMessage::LeaseGranted {
+                    leases: vec![grant("card0", 1, "HDMI-A-1")],
+                },
+                &[card_fd.into()],
+            );
+
+            send(&mut stream, ServerMessage::DisplayRemoved(removed));
+            send(
+                &mut stream,
+                ServerMessage::LeaseRevoked {
+                    reason: RevokeReason::DeviceRemoved,
+                },
+            );
+
+            stream
+        }
+    });
+
+    let mut lease = Client::connect(server.path())
+        .unwrap()
+        .request_displays(&[], LeaseMode::AllOrNothing)
+        .unwrap();
+
+    assert_eq!(lease.next_event().unwrap(), Event::DisplayAdded(added));
+    assert_eq!(lease.next_event().unwrap(), Event::DisplayRemoved(removed));
+    assert_eq!(
+        lease.next_event().unwrap(),
+        Event::LeaseRevoked(RevokeReason::DeviceRemoved),
+    );
+
+    server.join();
+}
+
+#[test]
+fn revocations_arrive_as_events() {
+    let reasons = [
+        RevokeReason::DeviceRemoved,
+        RevokeReason::SeatRemoved,
+        RevokeReason::SessionEnded,
+        RevokeReason::SessionInactive,
+        RevokeReason::DisplayRemoved,
+        RevokeReason::Shutdown,
+        RevokeReason::HolderExited,
+    ];
+
+    for reason in reasons {
+        let server = TestServer::start(move |mut stream| {
+            accept_hello(&mut stream);
+            expect_request(&mut stream);
+
+            let (card_fd, _) = UnixStream::pair().unwrap();
+            send_with_fds(
+                &mut stream,
+                ServerMessage::LeaseGranted {
+                    leases: vec![grant("card0", 1, "HDMI-A-1")],
+                },
+                &[card_fd.into()],
+            );
+            send(&mut stream, ServerMessage::LeaseRevoked { reason });
+
+            stream
+        });
+
+        let mut lease = Client::connect(server.path())
+            .unwrap()
+            .request_displays(&[], LeaseMode::AllOrNothing)
+            .unwrap();
+
+        assert_eq!(
+            lease.next_event().unwrap(),
+            Event::LeaseRevoked(reason),
+            "{reason:?}",
+        );
+        server.join();
+    }
+}
+
+#[test]
+fn resumed_lease_replaces_the_cards() {
+    let server = TestServer::start(|mut stream| {
+        accept_hello(&mut stream);
+        expect_request(&mut stream);
+
+        let (card_fd, _) = UnixStream::pair().unwrap();
+        send_with_fds(
+            &mut stream,
+            ServerMessage::LeaseGranted {
+                leases: vec![grant("card0", 1, "HDMI-A-1")],
+            },
+            &[card_fd.into()],
+        );
+
+        send(&mut stream, ServerMessage::LeaseSuspended);
+
+        let (resumed_fd, resumed_end) = UnixStream::pair().unwrap();
+        send_with_fds(
+            &mut stream,
+            ServerMessage::LeaseResumed {
+                leases: vec![grant("card0", 2, "HDMI-A-1")],
+            },
+            &[resumed_fd.into()],
+        );
+
+        (stream, resumed_end)
+    });
+
+    let mut lease = Client::connect(server.path())
+        .unwrap()
+        .request_displays(&[], LeaseMode::AllOrNothing)
+        .unwrap();
+
+    assert_eq!(lease.next_event().unwrap(), Event::LeaseSuspended);
+    assert!(lease.cards().is_empty());
+
+    assert_eq!(lease.next_event().unwrap(), Event::LeaseResumed);
+    let (_stream, mut resumed_end) = server.join();
+
+    let cards = lease.cards();
+    assert_eq!(cards.len(), 1);
+    assert_eq!(cards[0].grant, grant("card0", 2, "HDMI-A-1"));
+
+    let mut fd = UnixStream::from(cards[0].fd.try_clone().unwrap());
+    fd.write_all(b"r").unwrap();
+
+    let mut received = [0];
+    resumed_end.read_exact(&mut received).unwrap();
+    assert_eq!(received[0], b'r');
+}
+
+#[test]
+fn release_waits_for_the_server() {
+    let server = TestServer::start(|mut stream| {
+        accept_hello(&mut stream);
+        expect_request(&mut stream);
+
+        let (card_fd, _) = UnixStream::pair().unwrap();
+        send_with_fds(
+            &mut stream,
+            ServerMessage::LeaseGranted {
+                leases: vec![grant("card0", 1, "HDMI-A-1")],
+            },
+            &[card_fd.into()],
+        );
+
+        assert!(matches!(recv(&mut stream), ClientMessage::ReleaseDisplays));
+        send(
+            &mut stream,
+            ServerMessage::LeaseRevoked {
+                reason: RevokeReason::Released,
+            },
+        );
+    });
+
+    let lease = Client::connect(server.path())
+        .unwrap()
+        .request_displays(&[], LeaseMode::AllOrNothing)
+        .unwrap();
+
+    lease.release().unwrap();
+    server.join();
+}
+
+#[test]
+fn dropping_the_lease_closes_the_connection() {
+    let server = TestServer::start(|mut stream| {
+        accept_hello(&mut stream);
+        expect_request(&mut stream);
+
+        let (card_fd, _) = UnixStream::pair().unwrap();
+        send_with_fds(
+            &mut stream,
+            ServerMessage::LeaseGranted {
+                leases: vec![grant("card0", 1, "HDMI-A-1")],
+            },
+            &[card_fd.into()],
+        );
+
+        let mut rest = vec![];
+        stream.read_to_end(&mut rest).unwrap();
+        rest
+    });
+
+    let lease = Client::connect(server.path())
+        .unwrap()
+        .request_displays(&[], LeaseMode::AllOrNothing)
+        .unwrap();
+    drop(lease);
+
+    assert!(server.join().is_empty());
+}
+
+#[test]
+fn default_socket_comes_from_the_environment() {
+    let server = TestServer::start(|mut stream| accept_hello(&mut stream));
+
+    env::set_var(SOCKET_ENV, server.path());
+    let client = Client::connect_default();
+    env::remove_var(SOCKET_ENV);
+
+    assert_eq!(client.unwrap().protocol_version(), PROTOCOL_VERSION);
+    server.join();
+
+    assert!(matches!(
+        Client::connect_default(),
+        Err(ClientError::NoSocketPath)
+    ));
+}
@@ -0,0 +1,119 @@
+//! Helpers shared by the client tests.
+#![allow(dead_code)]
+
+use std::{
+    env,
+    io::Write,
+    os::{
+        fd::{AsRawFd, OwnedFd},
+        unix::net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    sync::atomic::{AtomicUsize, Ordering},
+    thread::{self, JoinHandle},
+};
+
+use display_distributor::{
+    framing, CardInfo, ClientMessage, DisplayId, LeaseGrant, LeasedConnector, ServerMessage,
+    PROTOCOL_VERSION,
+};
+use sendfd::SendWithFd;
+
+/// A stand-in server that runs `serve` on the first connection.
+pub struct TestServer<T> {
+    path: PathBuf,
+    thread: JoinHandle<T>,
+}
+
+impl<T: Send + 'static> TestServer<T> {
+    pub fn start(serve: impl FnOnce(UnixStream) -> T + Send + 'static) -> Self {
+        static NEXT_ID: AtomicUsize = AtomicUsize::new(0);
+
+        let path = env::temp_dir().join(format!(
+            "display-distributor-test-{}-{}.sock",
+            std::process::id(),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed),
+        ));
+        let listener = UnixListener::bind(&path).unwrap();
+
+        let thread = thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            serve(stream)
+        });
+
+        Self { path, thread }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn join(self) -> T {
+        let result = self.thread.join().unwrap();
+        std::fs::remove_file(&self.path).unwrap();
+
+        result
+    }
+}
+
+pub fn recv(stream: &mut UnixStream) -> ClientMessage {
+    framing::read_frame(stream).unwrap()
+}
+
+pub fn send(stream: &mut UnixStream, message: ServerMessage) {
+    framing::write_frame(stream, &message).unwrap();
+}
+
+pub fn send_with_fds(stream: &mut UnixStream, message: ServerMessage, fds: &[OwnedFd]) {
+    let frame = framing::encode(&message).unwrap();
+    let fds: Vec<_> = fds.iter().map(AsRawFd::as_raw_fd).collect();
+
+    let sent = stream.send_with_fd(&frame, &fds).unwrap();
+    stream.write_all(&frame[sent..]).unwrap();
+}
+
+pub fn accept_hello(stream: &mut UnixStream) {
+    let ClientMessage::Hello {
+        protocol_version,
+        capabilities,
+        ..
+    } = recv(stream)
+    else {
+        panic!("The client must start with Hello");
+    };
+    assert_eq!(protocol_version, PROTOCOL_VERSION);
+
+    send(
+        stream,
+        ServerMessage::Hello {
+            protocol_version,
+            capabilities,
+        },
+    );
+}
+
+pub fn expect_request(stream: &mut UnixStream) -> Vec<DisplayId> {
+    let ClientMessage::RequestDisplays { displays, .. } = recv(stream) else {
+        panic!("Expected RequestDisplays");
+    };
+
+    displays
+}
+
+pub fn grant(sysname: &str, lessee_id: u32, connector: &str) -> LeaseGrant {
+    LeaseGrant {
+        card: CardInfo {
+            devnode: PathBuf::from(format!("/dev/dri/{sysname}")),
+            sysname: sysname.to_string(),
+            driver: Some("test".to_string()),
+            pci: None,
+        },
+        lessee_id,
+        connectors: vec![LeasedConnector {
+            handle: lessee_id * 10,
+            name: connector.to_string(),
+        }],
+        crtcs: vec![lessee_id * 10 + 1],
+        planes: vec![lessee_id * 10 + 2],
+    }
+}
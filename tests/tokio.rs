@@ -0,0 +1,152 @@
+#![cfg(feature = "async")]
+
+mod common;
+
+use std::{
+    future::{self, Future},
+    io::{Read, Write},
+    os::unix::net::UnixStream,
+    pin::Pin,
+};
+
+use display_distributor::{
+    client::{
+        tokio::{Client, Lease},
+        ClientError, Event,
+    },
+    ClientMessage, DisplayId, LeaseMode, RevokeReason, ServerMessage, PROTOCOL_VERSION,
+};
+use futures_core::Stream;
+
+use common::{accept_hello, expect_request, grant, recv, send, send_with_fds, TestServer};
+
+fn block_on<F: Future>(future: F) -> F::Output {
+    tokio::runtime::Builder::new_current_thread()
+        .enable_io()
+        .build()
+        .unwrap()
+        .block_on(future)
+}
+
+/// The next item of the lease event stream.
+async fn next_item(lease: &mut Lease) -> Option<Result<Event, ClientError>> {
+    future::poll_fn(|cx| Pin::new(&mut *lease).poll_next(cx)).await
+}
+
+/// Grants a lease on `card0` and returns the other end of the lease fd.
+fn grant_card0(stream: &mut UnixStream) -> UnixStream {
+    let (card_fd, card_end) = UnixStream::pair().unwrap();
+    send_with_fds(
+        stream,
+        ServerMessage::LeaseGranted {
+            leases: vec![grant("card0", 1, "HDMI-A-1")],
+        },
+        &[card_fd.into()],
+    );
+
+    card_end
+}
+
+#[test]
+fn lease_carries_fds_with_their_grants() {
+    let server = TestServer::start(|mut stream| {
+        accept_hello(&mut stream);
+        assert_eq!(
+            expect_request(&mut stream),
+            [DisplayId::Connector("HDMI-A-1".to_string())],
+        );
+
+        let mut card_end = grant_card0(&mut stream);
+
+        let mut received = [0];
+        card_end.read_exact(&mut received).unwrap();
+        received[0]
+    });
+
+    block_on(async {
+        let client = Client::connect(server.path()).await.unwrap();
+        assert_eq!(client.protocol_version(), PROTOCOL_VERSION);
+
+        let lease = client
+            .request_displays(
+                &[DisplayId::Connector("HDMI-A-1".to_string())],
+                LeaseMode::AllOrNothing,
+            )
+            .await
+            .unwrap();
+
+        let cards = lease.cards();
+        assert_eq!(cards.len(), 1);
+        assert_eq!(cards[0].grant, grant("card0", 1, "HDMI-A-1"));
+
+        let mut fd = UnixStream::from(cards[0].fd.try_clone().unwrap());
+        fd.write_all(b"t").unwrap();
+    });
+
+    assert_eq!(server.join(), b't');
+}
+
+#[test]
+fn pushed_events_arrive_through_the_stream() {
+    let server = TestServer::start(|mut stream| {
+        accept_hello(&mut stream);
+        expect_request(&mut stream);
+        grant_card0(&mut stream);
+
+        send(
+            &mut stream,
+            ServerMessage::LeaseRevoked {
+                reason: RevokeReason::DeviceRemoved,
+            },
+        );
+    });
+
+    block_on(async {
+        let mut lease = Client::connect(server.path())
+            .await
+            .unwrap()
+            .request_displays(&[], LeaseMode::AllOrNothing)
+            .await
+            .unwrap();
+
+        assert_eq!(
+            next_item(&mut lease).await.unwrap().unwrap(),
+            Event::LeaseRevoked(RevokeReason::DeviceRemoved),
+        );
+
+        // The stream ends once the server closes the connection.
+        assert!(next_item(&mut lease).await.is_none());
+    });
+
+    server.join();
+}
+
+#[test]
+fn release_waits_for_the_server() {
+    let server = TestServer::start(|mut stream| {
+        accept_hello(&mut stream);
+        expect_request(&mut stream);
+        grant_card0(&mut stream);
+
+        assert!(matches!(recv(&mut stream), ClientMessage::ReleaseDisplays));
+        send(
+            &mut stream,
+            ServerMessage::LeaseRevoked {
+                reason: RevokeReason::Released,
+            },
+        );
+    });
+
+    block_on(async {
+        let lease = Client::connect(server.path())
+            .await
+            .unwrap()
+            .request_displays(&[], LeaseMode::AllOrNothing)
+            .await
+            .unwrap();
+
+        lease.release().await.unwrap();
+    });
+
+    server.join();
+}